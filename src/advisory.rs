@@ -0,0 +1,270 @@
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// AdvisoryKind is the kind of a RustSec advisory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AdvisoryKind {
+    /// a security vulnerability
+    Vulnerability,
+    /// the crate is not maintained anymore
+    Unmaintained,
+    /// the crate exposes an unsound API (undefined behavior from safe code)
+    Unsound,
+    /// any other informational advisory
+    Notice,
+}
+
+/// Advisory is a RustSec advisory affecting a version of a dependency.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Advisory {
+    /// the identifier of the advisory (for example `RUSTSEC-2020-0071`)
+    pub id: String,
+    /// a one-line summary
+    pub title: String,
+    /// when the advisory was published (`YYYY-MM-DD`)
+    pub date: String,
+    /// the kind of advisory
+    pub kind: AdvisoryKind,
+    /// other identifiers of the advisory (for example CVE or GHSA numbers)
+    pub aliases: Vec<String>,
+    /// the page of the advisory on rustsec.org
+    pub url: String,
+    /// the versions that are not affected anymore (semver requirements), empty if none
+    pub patched_versions: Vec<String>,
+}
+
+/// the TOML front matter of an advisory (only what we use)
+#[derive(Deserialize)]
+struct AdvisoryFile {
+    advisory: AdvisoryMetadata,
+    #[serde(default)]
+    versions: AdvisoryVersions,
+}
+
+#[derive(Deserialize)]
+struct AdvisoryMetadata {
+    id: String,
+    package: String,
+    date: String,
+    #[serde(default)]
+    aliases: Vec<String>,
+    informational: Option<String>,
+    withdrawn: Option<String>,
+}
+
+#[derive(Default, Deserialize)]
+struct AdvisoryVersions {
+    #[serde(default)]
+    patched: Vec<String>,
+    #[serde(default)]
+    unaffected: Vec<String>,
+}
+
+/// an advisory, with the versions it does not affect
+struct Entry {
+    advisory: Advisory,
+    not_affected: Vec<VersionReq>,
+}
+
+/// AdvisoryDatabase is a local checkout of the RustSec advisory database
+/// (https://github.com/RustSec/advisory-db), indexed by crate name.
+pub struct AdvisoryDatabase {
+    advisories: HashMap<String, Vec<Entry>>,
+}
+
+impl AdvisoryDatabase {
+    /// reads every crate advisory of the database at `path` (withdrawn advisories are ignored)
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        let invalid = |path: &Path, err: String| Error::AdvisoryDb(path.to_path_buf(), err);
+        let crates_dir = path.join("crates");
+        if !crates_dir.is_dir() {
+            return Err(invalid(path, "no crates folder found".to_string()));
+        }
+
+        // crates/<name>/RUSTSEC-YYYY-NNNN.md
+        let mut files: Vec<PathBuf> = Vec::new();
+        for crate_dir in fs::read_dir(&crates_dir)? {
+            let crate_dir = crate_dir?.path();
+            if !crate_dir.is_dir() {
+                continue;
+            }
+            for file in fs::read_dir(&crate_dir)? {
+                let file = file?.path();
+                if file.extension().and_then(|extension| extension.to_str()) == Some("md") {
+                    files.push(file);
+                }
+            }
+        }
+
+        let mut advisories: HashMap<String, Vec<Entry>> = HashMap::new();
+        for file in files {
+            let content = fs::read_to_string(&file)?;
+            if let Some((package, entry)) =
+                parse_advisory(&content).map_err(|err| invalid(&file, err))?
+            {
+                advisories.entry(package).or_default().push(entry);
+            }
+        }
+        for entries in advisories.values_mut() {
+            entries.sort_by(|a, b| a.advisory.id.cmp(&b.advisory.id));
+        }
+        Ok(Self { advisories })
+    }
+
+    /// the advisories affecting a version of a crate
+    pub fn advisories_for(&self, name: &str, version: &str) -> Vec<Advisory> {
+        let version = match Version::parse(version) {
+            Ok(version) => version,
+            Err(_) => return Vec::new(),
+        };
+        self.advisories
+            .get(name)
+            .into_iter()
+            .flatten()
+            .filter(|entry| !entry.not_affected.iter().any(|req| req.matches(&version)))
+            .map(|entry| entry.advisory.clone())
+            .collect()
+    }
+}
+
+/// parses an advisory file, returns `None` if it was withdrawn
+fn parse_advisory(content: &str) -> Result<Option<(String, Entry)>, String> {
+    // a TOML front matter in a code block, followed by the title and description in markdown
+    let rest = content
+        .trim_start()
+        .strip_prefix("```toml")
+        .ok_or("no TOML front matter")?;
+    let end = rest.find("\n```").ok_or("unterminated TOML front matter")?;
+    let (front_matter, markdown) = (&rest[..end], &rest[end + 4..]);
+    let file: AdvisoryFile = toml::from_str(front_matter).map_err(|err| err.to_string())?;
+    let metadata = file.advisory;
+    if metadata.withdrawn.is_some() {
+        return Ok(None);
+    }
+
+    let title = markdown
+        .lines()
+        .find_map(|line| line.strip_prefix("# "))
+        .map(|title| title.trim().to_string())
+        .unwrap_or_default();
+    let kind = match metadata.informational.as_deref() {
+        None => AdvisoryKind::Vulnerability,
+        Some("unmaintained") => AdvisoryKind::Unmaintained,
+        Some("unsound") => AdvisoryKind::Unsound,
+        Some(_) => AdvisoryKind::Notice,
+    };
+    let not_affected = file
+        .versions
+        .patched
+        .iter()
+        .chain(file.versions.unaffected.iter())
+        .map(|req| VersionReq::parse(req).map_err(|err| format!("{}: {}", req, err)))
+        .collect::<Result<_, _>>()?;
+    let advisory = Advisory {
+        url: format!("https://rustsec.org/advisories/{}.html", metadata.id),
+        id: metadata.id,
+        title,
+        date: metadata.date,
+        kind,
+        aliases: metadata.aliases,
+        patched_versions: file.versions.patched,
+    };
+    Ok(Some((
+        metadata.package,
+        Entry {
+            advisory,
+            not_affected,
+        },
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// an advisory file for `package` with the given TOML lines in its front matter
+    fn advisory_file(id: &str, package: &str, front_matter: &str) -> String {
+        format!(
+            "```toml\n\
+             [advisory]\n\
+             id = \"{}\"\n\
+             package = \"{}\"\n\
+             date = \"2021-01-01\"\n\
+             {}\n\
+             ```\n\n\
+             # Title of {}\n\n\
+             Description.\n",
+            id, package, front_matter, id
+        )
+    }
+
+    /// the ids of the advisories affecting a version
+    fn ids(db: &AdvisoryDatabase, name: &str, version: &str) -> Vec<String> {
+        db.advisories_for(name, version)
+            .into_iter()
+            .map(|advisory| advisory.id)
+            .collect()
+    }
+
+    #[test]
+    fn version_matching() {
+        let dir = TempDir::new("dephell_advisory").unwrap();
+        let crate_dir = dir.path().join("crates/foo");
+        fs::create_dir_all(&crate_dir).unwrap();
+        let advisories = [
+            (
+                "RUSTSEC-2021-0001",
+                "[versions]\npatched = [\">= 1.2.0\"]\nunaffected = [\"< 1.0.0\"]",
+            ),
+            (
+                "RUSTSEC-2021-0002",
+                "informational = \"unmaintained\"\n[versions]\npatched = []",
+            ),
+            ("RUSTSEC-2021-0003", "withdrawn = \"2021-02-01\""),
+        ];
+        for (id, front_matter) in advisories.iter() {
+            fs::write(
+                crate_dir.join(format!("{}.md", id)),
+                advisory_file(id, "foo", front_matter),
+            )
+            .unwrap();
+        }
+        let db = AdvisoryDatabase::open(dir.path()).unwrap();
+
+        // patched and unaffected versions are not affected, withdrawn advisories are ignored
+        assert_eq!(ids(&db, "foo", "0.9.0"), vec!["RUSTSEC-2021-0002"]);
+        assert_eq!(
+            ids(&db, "foo", "1.1.5"),
+            vec!["RUSTSEC-2021-0001", "RUSTSEC-2021-0002"]
+        );
+        assert_eq!(ids(&db, "foo", "1.2.0"), vec!["RUSTSEC-2021-0002"]);
+        assert!(ids(&db, "bar", "1.1.5").is_empty());
+        assert!(ids(&db, "foo", "not a version").is_empty());
+
+        let advisories = db.advisories_for("foo", "1.1.5");
+        assert_eq!(advisories[0].title, "Title of RUSTSEC-2021-0001");
+        assert_eq!(advisories[0].kind, AdvisoryKind::Vulnerability);
+        assert_eq!(advisories[0].patched_versions, vec![">= 1.2.0"]);
+        assert_eq!(advisories[1].kind, AdvisoryKind::Unmaintained);
+    }
+
+    #[test]
+    fn invalid_advisories() {
+        assert!(parse_advisory("# no front matter").is_err());
+        assert!(parse_advisory("```toml\n[advisory]\n").is_err());
+        let content = advisory_file(
+            "RUSTSEC-2021-0001",
+            "foo",
+            "[versions]\npatched = [\"not a requirement\"]",
+        );
+        assert!(parse_advisory(&content).is_err());
+    }
+}
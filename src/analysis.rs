@@ -1,16 +1,32 @@
 use camino::Utf8PathBuf;
-use guppy::graph::{DependencyDirection, PackageGraph, PackageLink};
-use guppy::{MetadataCommand, PackageId};
+use chrono::{DateTime, NaiveDate, Utc};
+use guppy::graph::{DependencyDirection, PackageLink, PackageMetadata};
+use guppy::{CargoMetadata, MetadataCommand, PackageId};
+use rayon::prelude::*;
 use regex::Regex;
+use schemars::{schema::RootSchema, JsonSchema};
 use serde::{Deserialize, Serialize};
 use std::collections::{
     hash_map::{Entry, HashMap},
-    HashSet,
+    BTreeMap, BTreeSet, HashSet,
 };
 use std::iter::FromIterator;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 use tempdir::TempDir;
 
+use crate::advisory::{Advisory, AdvisoryDatabase};
+use crate::audit::{Audit, AuditLedger, AuditStatus, AUDIT_FILE_NAME};
+use crate::cache::Cache;
+use crate::crev::{CrevProofs, CrevReview};
 use crate::metrics;
+use crate::modules;
+use crate::resolve::{DependencyKind, FeatureSelection, ResolvedGraph};
+use crate::risk::{self, RiskScore};
+use crate::source::{SourceVerification, SourceVerifier};
+use crate::unsafety::UnsafeMetrics;
+use crate::vet::{VetStatus, VetStore, VET_DIR_NAME};
+use crate::Error;
 
 //
 // Essential Structs
@@ -18,19 +34,18 @@ use crate::metrics;
 //
 
 /// PackageRisk contains information about a package after analysis.
-/// Note that the word "total" means that it includes transitive dependencies.
+/// Note that the word "total" means that it includes transitive dependencies (each counted once).
 #[rustfmt::skip]
-#[derive(Default, Serialize, Deserialize, Clone)]
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone)]
 pub struct PackageRisk {
 
   // metadata
   // --------
 
   /// name of the dependency
-  #[serde(skip)]
   pub name: String,
-  /// potentially different versions are pulled (bad)
-  pub versions: HashSet<String>,
+  /// version of the dependency (each version is analyzed separately)
+  pub version: String,
   /// link to its repository
   pub repo: Option<String>,
   /// description from Cargo.toml
@@ -39,39 +54,74 @@ pub struct PackageRisk {
   // useful for analysis
   // -------------------
 
-  /// path to the actual source code on disk
-  #[serde(skip)]
+  /// path to the Cargo.toml of the package on disk
+  #[schemars(with = "String")]
   pub manifest_path: Utf8PathBuf,
-  /// have we calculated the total LOCs for this dep?
-  #[serde(skip)]
-  pub total_calculated: bool,
 
   // analysis result
   // ---------------
 
   /// is this an internal package?
   pub internal: bool,
-  /// is this dependency used for the host target and features?
-  pub used: bool,
+  /// is this dependency used for the selected targets and features?
+  /// (`None` if the dependencies were not built)
+  pub used: Option<bool>,
+  /// the kinds of dependency (normal, build, dev) through which it is imported
+  pub dependency_kinds: BTreeSet<DependencyKind>,
+  /// is this a procedural macro? (executed at compile time)
+  pub proc_macro: bool,
+  /// does this package have a build script? (executed at compile time)
+  pub build_script: bool,
+  /// the native library this package links (the `links` key of its Cargo.toml)
+  pub links: Option<String>,
+  /// is this a -sys crate? (it has a `links` key, a `-sys` name, or links native libraries)
+  pub sys_crate: bool,
+  /// the native libraries linked by its build script (`cargo:rustc-link-lib`),
+  /// `None` if the dependencies were not built
+  pub native_libraries: Option<BTreeSet<String>>,
   
-  /// direct dependencies
-  pub direct_dependencies: HashSet<String>,
+  /// direct dependencies (see `package_key`)
+  pub direct_dependencies: BTreeSet<String>,
   /// transitive dependencies (not including this dependency)
-  pub transitive_dependencies: HashSet<String>,
+  pub transitive_dependencies: BTreeSet<String>,
   /// number of root crates that import this package
   pub root_importers: Vec<String>,
   /// total number of transitive third party dependencies imported
   /// by this dependency, and only by this dependency
   pub exclusive_deps_introduced: Vec<String>,
-  /// (total) number of non-rust lines-of-code
+  /// number of lines-of-code (in all languages, rust included)
   pub loc: u64,
+  /// total number of lines-of-code (in all languages, rust included)
   pub total_loc: u64,
-  /// (total) number of rust lines-of-code
+  /// number of rust lines-of-code
   pub rust_loc: u64,
+  /// total number of rust lines-of-code
   pub total_rust_loc: u64,
-  /// (total) number of lines of unsafe code
+  /// number of lines inside unsafe regions (unsafe blocks and bodies of unsafe functions)
   pub unsafe_loc: u64,
+  /// total number of lines inside unsafe regions
   pub total_unsafe_loc: u64,
+  /// number of unsafe constructs, found by parsing the rust files
+  pub unsafe_metrics: UnsafeMetrics,
+  /// total number of unsafe constructs
+  pub total_unsafe_metrics: UnsafeMetrics,
+  /// number of lines of native code (C, C++, Objective-C and assembly) vendored
+  /// (also counted in `loc`)
+  pub native_loc: u64,
+  /// total number of lines of native code vendored
+  pub total_native_loc: u64,
+  /// number of places that can panic (explicit panics, unwraps, expects and indexing,
+  /// according to clippy), `None` if clippy is not installed
+  pub panic_sites: Option<u64>,
+  /// total number of places that can panic
+  pub total_panic_sites: Option<u64>,
+  /// the files of the package with their metrics (see `FileMetrics`)
+  pub files: BTreeMap<String, FileMetrics>,
+  // network metrics
+  // ---------------
+  // these are `None` if they could not be obtained
+  // (see `AnalysisReport::unavailable_metrics`)
+
   /// number of github stars, if any
   pub stargazers_count: Option<u64>,
   /// active contributors on github (in the last 6 months)
@@ -79,7 +129,100 @@ pub struct PackageRisk {
   /// number of dependent crates on crates.io
   pub crates_io_dependent: Option<u64>,
   /// last update according to crates.io
-  pub crates_io_last_updated: Option<String>,
+  pub crates_io_last_updated: Option<NaiveDate>,
+  /// is this version yanked from crates.io?
+  pub crates_io_yanked: Option<bool>,
+
+  // advisories
+  // ----------
+
+  /// the RustSec advisories affecting this version
+  /// (`None` if no advisory database was given)
+  pub advisories: Option<Vec<Advisory>>,
+
+  // audits
+  // ------
+  // these are `None` for workspace crates, or if there is no audit ledger
+
+  /// the commit of its repository this version was published from (if known)
+  pub vcs_commit: Option<String>,
+  /// the audits of this version recorded in the audit ledger
+  pub audits: Vec<Audit>,
+  /// how much of this version was audited
+  pub audit_status: Option<AuditStatus>,
+  /// number of lines-of-code not fully audited
+  pub unaudited_loc: Option<u64>,
+  /// total number of lines-of-code not fully audited
+  /// (each transitive dependency is counted once)
+  pub total_unaudited_loc: Option<u64>,
+
+  // cargo-vet and crev
+  // ------------------
+  // these are `None` for workspace crates, or if there is no cargo-vet store (or crev proofs)
+
+  /// what cargo-vet knows about this version
+  pub vet: Option<VetStatus>,
+  /// number of lines-of-code not vetted by cargo-vet
+  pub unvetted_loc: Option<u64>,
+  /// total number of lines-of-code not vetted by cargo-vet
+  /// (each transitive dependency is counted once)
+  pub total_unvetted_loc: Option<u64>,
+  /// the crev reviews of this version
+  pub crev_reviews: Option<Vec<CrevReview>>,
+
+  // source verification
+  // -------------------
+
+  /// the comparison of the published crate with its repository
+  /// (`None` for workspace crates, or if the sources are not verified)
+  pub source_verification: Option<SourceVerification>,
+
+  // risk
+  // ----
+
+  /// the risk score of this dependency, computed from the metrics above
+  pub risk: RiskScore,
+}
+
+/// DuplicateVersion describes one of the versions of a dependency that gets pulled
+/// several times (with different versions) in the dependency graph.
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct DuplicateVersion {
+    /// the version pulled
+    pub version: String,
+    /// the key of that version in the analysis result (see `package_key`)
+    pub package: String,
+    /// the root crates that end up importing this version
+    pub root_importers: Vec<String>,
+    /// for each root importer, a path of packages leading to this version
+    /// (starting with the root crate and ending with this version)
+    pub paths: Vec<Vec<String>>,
+}
+
+/// DependencyEdge is a dependency of a package (or of a root crate) on another package,
+/// as it gets built (a dependency declared with several kinds has one edge per kind).
+#[derive(Serialize, Deserialize, JsonSchema, Clone)]
+pub struct DependencyEdge {
+    /// the package importing the dependency (see `package_key`)
+    pub from: String,
+    /// the dependency (see `package_key`)
+    pub to: String,
+    /// how the dependency is declared (proc macros are build dependencies)
+    pub kind: DependencyKind,
+}
+
+/// FileMetrics are the metrics of a file of a package, the package's own metrics
+/// are the sum of these.
+#[derive(Default, Serialize, Deserialize, JsonSchema, Clone)]
+pub struct FileMetrics {
+    /// number of lines-of-code
+    pub loc: u64,
+    /// number of rust lines-of-code
+    pub rust_loc: u64,
+    /// number of lines inside unsafe regions
+    pub unsafe_loc: u64,
+    /// number of unsafe constructs
+    pub unsafe_metrics: UnsafeMetrics,
 }
 
 //
@@ -87,39 +230,560 @@ pub struct PackageRisk {
 // ------
 //
 
+/// Returns the key used to identify a package in the analysis result.
+/// Packages are keyed by name and version as different versions of the same
+/// dependency can be pulled at the same time.
+pub fn package_key(package: &PackageMetadata) -> String {
+    format!("{} {}", package.name(), package.version())
+}
+
 fn create_or_update_dependency(
     analysis_result: &mut HashMap<PackageId, PackageRisk>,
     dep_link: &PackageLink,
 ) {
-    match analysis_result.entry(dep_link.to().id().to_owned()) {
-        Entry::Occupied(mut entry) => {
-            let package_risk = entry.get_mut();
-            package_risk
-                .versions
-                .insert(dep_link.to().version().to_string());
+    if let Entry::Vacant(entry) = analysis_result.entry(dep_link.to().id().to_owned()) {
+        let package_risk = PackageRisk {
+            name: dep_link.to().name().to_owned(),
+            version: dep_link.to().version().to_string(),
+            repo: dep_link.to().repository().map(|x| x.to_owned()),
+            description: dep_link.to().description().map(|x| x.to_owned()),
+            manifest_path: dep_link.to().manifest_path().to_path_buf(),
+            internal: dep_link.to().in_workspace(),
+            proc_macro: dep_link.to().is_proc_macro(),
+            build_script: dep_link.to().has_build_script(),
+            links: dep_link.to().links().map(|x| x.to_owned()),
+            ..Default::default()
+        };
+        entry.insert(package_risk);
+    }
+}
+
+/// adds up a metric over a package and its transitive dependencies (each counted once),
+/// `None` if the metric is not available for the package
+fn transitive_total(
+    analysis_result: &BTreeMap<String, PackageRisk>,
+    package_risk: &PackageRisk,
+    metric: impl Fn(&PackageRisk) -> Option<u64>,
+) -> Option<u64> {
+    let transitive_dependencies = package_risk.transitive_dependencies.iter();
+    let total = transitive_dependencies
+        .filter_map(|dep| metric(analysis_result.get(dep)?))
+        .sum::<u64>();
+    metric(package_risk).map(|own| own + total)
+}
+
+/// CheckedDependencies is what checking the workspace tells about the crates, besides metrics.
+#[derive(Default)]
+struct CheckedDependencies {
+    /// the dep-info files of the crates that were checked
+    dep_info_files: HashSet<PathBuf>,
+    /// the cfg options set by the build script of each package (with `cargo:rustc-cfg`)
+    script_cfgs: HashMap<PackageId, Vec<String>>,
+}
+
+/// checks the workspace (with clippy if it is installed) in `target_dir` to obtain the
+/// dep-info files of the crates that were checked, the cfg options set by build scripts,
+/// and the metrics that are only known once dependencies are compiled (the panic sites
+/// and the libraries linked by build scripts)
+fn check_dependencies(
+    options: &AnalysisOptions,
+    target_dir: &Path,
+    analysis_result: &mut HashMap<PackageId, PackageRisk>,
+    unavailable_metrics: &mut BTreeMap<String, String>,
+) -> Result<CheckedDependencies, Error> {
+    // clippy (if installed) replaces rustc to also report the places that can panic,
+    // its lints are forced so that they are reported for dependencies as well
+    let clippy = std::process::Command::new("clippy-driver")
+        .arg("--version")
+        .output()
+        .is_ok_and(|output| output.status.success());
+    let mut rustflags = "--cap-lints=warn".to_string();
+    let mut cmd = std::process::Command::new("cargo");
+    for package_risk in analysis_result.values_mut() {
+        package_risk.native_libraries = Some(BTreeSet::new());
+    }
+    if clippy {
+        for lint in PANIC_LINTS.iter() {
+            rustflags.push_str(" --force-warn ");
+            rustflags.push_str(lint);
         }
-        Entry::Vacant(entry) => {
-            let mut package_risk = PackageRisk::default();
-            package_risk.name = dep_link.to().name().to_owned();
-            package_risk
-                .versions
-                .insert(dep_link.to().version().to_string());
-            package_risk.repo = dep_link.to().repository().map(|x| x.to_owned());
-            package_risk.description = dep_link.to().description().map(|x| x.to_owned());
-            package_risk.manifest_path = dep_link.to().manifest_path().to_path_buf();
-            package_risk.internal = dep_link.to().in_workspace();
-            entry.insert(package_risk);
+        cmd.env("RUSTC_WRAPPER", "clippy-driver");
+        for package_risk in analysis_result.values_mut() {
+            package_risk.panic_sites = Some(0);
         }
-    };
+    } else {
+        for metric in ["panic_sites", "total_panic_sites"].iter() {
+            unavailable_metrics.insert(metric.to_string(), "clippy not installed".to_string());
+        }
+    }
+
+    let output = cmd
+        .env("RUSTFLAGS", rustflags)
+        .args([
+            "check",
+            "-vv",
+            "--message-format=json-diagnostic-rendered-ansi",
+        ])
+        .arg("--manifest-path")
+        .arg(&options.manifest_path)
+        .arg("--target-dir")
+        .arg(target_dir)
+        .args(options.features.cargo_args())
+        .args(
+            options
+                .targets
+                .iter()
+                .flat_map(|target| vec!["--target", target]),
+        )
+        // dev dependencies are only built with tests, examples and benches
+        .args(if options.include_dev {
+            &["--all-targets"][..]
+        } else {
+            &[]
+        })
+        .args(if options.offline {
+            &["--offline"][..]
+        } else {
+            &[]
+        })
+        .output()?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(Error::Build(stderr));
+    }
+
+    // .panic_sites - find the places that can panic by analyzing clippy's output
+    // .native_libraries - find the libraries linked by build scripts
+    // (the dep-info files of the crates that were checked are recorded as well, as a target
+    // directory that is kept between analyses can contain the ones of older analyses)
+    let mut checked = CheckedDependencies::default();
+    let output = std::io::Cursor::new(output.stdout);
+    for message in cargo_metadata::Message::parse_stream(output) {
+        match message {
+            Ok(cargo_metadata::Message::CompilerArtifact(artifact)) => {
+                let dep_info_files = artifact.filenames.iter().filter_map(|file| {
+                    let stem = file.file_stem()?.to_str()?;
+                    let stem = stem.strip_prefix("lib").unwrap_or(stem);
+                    Some(file.with_file_name(format!("{}.d", stem)))
+                });
+                checked.dep_info_files.extend(dep_info_files);
+            }
+            Ok(cargo_metadata::Message::CompilerMessage(msg)) => {
+                if let Some(code) = msg.message.code {
+                    let package_id = PackageId::new(msg.package_id.repr);
+                    if PANIC_LINTS.contains(&code.code.as_str()) {
+                        analysis_result.entry(package_id).and_modify(|r| {
+                            r.panic_sites = r.panic_sites.map(|panic_sites| panic_sites + 1)
+                        });
+                    }
+                }
+            }
+            Ok(cargo_metadata::Message::BuildScriptExecuted(script)) => {
+                let package_id = PackageId::new(script.package_id.repr);
+                checked
+                    .script_cfgs
+                    .entry(package_id.clone())
+                    .or_default()
+                    .extend(script.cfgs);
+                let linked_libs = script
+                    .linked_libs
+                    .iter()
+                    .map(|lib| lib.to_string_lossy().into_owned());
+                analysis_result.entry(package_id).and_modify(|r| {
+                    r.native_libraries
+                        .get_or_insert_default()
+                        .extend(linked_libs)
+                });
+            }
+            _ => (),
+        }
+    }
+
+    Ok(checked)
+}
+
+/// the target directory of a workspace (which guppy doesn't expose)
+fn workspace_target_dir(metadata: &CargoMetadata) -> Result<PathBuf, Error> {
+    let mut json = Vec::new();
+    metadata.serialize(&mut json)?;
+    let metadata: cargo_metadata::Metadata =
+        serde_json::from_slice(&json).map_err(guppy::Error::MetadataParseError)?;
+    Ok(metadata.target_directory)
 }
 
-/// Takes a `manifest_path` and produce an analysis stored in `analysis_result`.
+//
+// Options and Report
+// ==================
+//
+
+/// AnalysisOptions configures an analysis, it is created with a `manifest_path`
+/// and can be customized with its builder methods:
+///
+/// ```no_run
+/// use cargo_dephell::{analyze_repo, AnalysisOptions};
 ///
-/// Optionally, you can pass:
-/// - `proxy`, a proxy (used to query github to fetch number of stars)
-/// - `github_token`, a github personnal access token (PAT) used to query the github API
-///   this is useful due to github limiting queries that are not authenticated.
-/// - `to_ignore`, a list of direct dependencies to ignore.
+/// let options = AnalysisOptions::new("./Cargo.toml")
+///     .ignore(vec!["some-workspace-crate"])
+///     .targets(vec!["wasm32-unknown-unknown"]);
+/// let report = analyze_repo(&options).unwrap();
+/// ```
+///
+/// The options are recorded in the report, except for the github token
+/// and the proxy (which can contain credentials).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AnalysisOptions {
+    /// the path to the `Cargo.toml` to analyze (as given)
+    manifest_path: PathBuf,
+    /// the workspace crates to analyze (all of them if not set)
+    packages: Option<Vec<String>>,
+    /// the workspace crates not to analyze
+    #[serde(rename = "ignore")]
+    to_ignore: Option<Vec<String>>,
+    /// the features enabled on the workspace crates
+    features: FeatureSelection,
+    /// the target triples analyzed (any platform if empty)
+    targets: Vec<String>,
+    /// are the dev dependencies of the workspace crates analyzed?
+    include_dev: bool,
+    /// is the network access disabled?
+    offline: bool,
+    /// the weight of each metric in the risk score
+    risk_weights: BTreeMap<String, f64>,
+    /// the local checkout of the RustSec advisory database, if any
+    advisory_db: Option<PathBuf>,
+    /// the audit ledger (by default the `AUDIT.toml` next to the manifest, if any)
+    audit_file: Option<PathBuf>,
+    /// the cargo-vet store (by default the `supply-chain` folder next to the manifest, if any)
+    vet_dir: Option<PathBuf>,
+    /// the folder containing crev proofs, if any
+    crev_proofs: Option<PathBuf>,
+    /// the crev id whose web of trust gives the trust level of reviewers, if any
+    crev_id: Option<String>,
+    /// are the published crates compared with their repositories?
+    verify_sources: bool,
+    /// the folder containing clones of the repositories of the dependencies, if any
+    source_mirror: Option<PathBuf>,
+    /// the target directory in which dependencies are checked (a temporary one if not set)
+    target_dir: Option<PathBuf>,
+    /// is the `dephell` folder of the workspace's target directory used to check dependencies?
+    reuse_target_dir: bool,
+    /// are the metrics obtained without checking the dependencies?
+    no_build: bool,
+    #[serde(skip)]
+    github_token: Option<(String, String)>,
+    #[serde(skip)]
+    proxy: Option<String>,
+    #[serde(skip)]
+    cache_dir: Option<PathBuf>,
+    #[serde(skip)]
+    cache_ttl: Duration,
+    #[serde(skip)]
+    refresh: bool,
+    #[serde(skip)]
+    jobs: Option<usize>,
+    #[serde(skip)]
+    quiet: bool,
+}
+
+impl AnalysisOptions {
+    /// analyzes the crate or workspace at `manifest_path` (a path to a `Cargo.toml`)
+    pub fn new(manifest_path: impl Into<PathBuf>) -> Self {
+        Self {
+            manifest_path: manifest_path.into(),
+            packages: None,
+            to_ignore: None,
+            features: FeatureSelection::default(),
+            targets: Vec::new(),
+            include_dev: false,
+            offline: false,
+            risk_weights: risk::default_weights(),
+            advisory_db: None,
+            audit_file: None,
+            vet_dir: None,
+            crev_proofs: None,
+            crev_id: None,
+            verify_sources: false,
+            source_mirror: None,
+            target_dir: None,
+            reuse_target_dir: false,
+            no_build: false,
+            github_token: None,
+            proxy: None,
+            cache_dir: Cache::default_dir(),
+            cache_ttl: Duration::from_secs(24 * 60 * 60),
+            refresh: false,
+            jobs: None,
+            quiet: true,
+        }
+    }
+
+    /// only analyze these workspace crates
+    pub fn packages<S: Into<String>>(mut self, packages: impl IntoIterator<Item = S>) -> Self {
+        self.packages = Some(packages.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// do not analyze these workspace crates (ignored if `packages` is used)
+    pub fn ignore<S: Into<String>>(mut self, to_ignore: impl IntoIterator<Item = S>) -> Self {
+        self.to_ignore = Some(to_ignore.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// the features to enable on the workspace crates (like cargo's `--features`,
+    /// `--all-features` and `--no-default-features`). Only the dependencies that end up being
+    /// built with this selection of features are analyzed.
+    pub fn features(mut self, features: FeatureSelection) -> Self {
+        self.features = features;
+        self
+    }
+
+    /// the target triples to analyze (like cargo's `--target`). If none are given,
+    /// dependencies of every platform are included but only the host is built.
+    pub fn targets<S: Into<String>>(mut self, targets: impl IntoIterator<Item = S>) -> Self {
+        self.targets = targets.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// also analyze the dev dependencies of the workspace crates
+    pub fn include_dev(mut self, include_dev: bool) -> Self {
+        self.include_dev = include_dev;
+        self
+    }
+
+    /// do not access the network: no metric is obtained from crates.io or github,
+    /// and cargo is run with `--offline` (dependencies must already be downloaded)
+    pub fn offline(mut self, offline: bool) -> Self {
+        self.offline = offline;
+        self
+    }
+
+    /// overrides the weight of some metrics in the risk score (see `risk::default_weights`),
+    /// a weight of zero removes the metric from the score
+    pub fn risk_weights<S: Into<String>>(
+        mut self,
+        risk_weights: impl IntoIterator<Item = (S, f64)>,
+    ) -> Self {
+        self.risk_weights.extend(
+            risk_weights
+                .into_iter()
+                .map(|(metric, weight)| (metric.into(), weight)),
+        );
+        self
+    }
+
+    /// a local checkout of the RustSec advisory database
+    /// (https://github.com/RustSec/advisory-db) used to find the advisories affecting
+    /// each dependency (no network access is needed)
+    pub fn advisory_db(mut self, advisory_db: impl Into<PathBuf>) -> Self {
+        self.advisory_db = Some(advisory_db.into());
+        self
+    }
+
+    /// the audit ledger recording which dependencies were reviewed
+    /// (by default the `AUDIT.toml` next to the manifest, if there is one)
+    pub fn audit_file(mut self, audit_file: impl Into<PathBuf>) -> Self {
+        self.audit_file = Some(audit_file.into());
+        self
+    }
+
+    /// the cargo-vet store (the folder containing `audits.toml`, `config.toml` and
+    /// `imports.lock`), by default the `supply-chain` folder next to the manifest (if any)
+    pub fn vet_dir(mut self, vet_dir: impl Into<PathBuf>) -> Self {
+        self.vet_dir = Some(vet_dir.into());
+        self
+    }
+
+    /// a folder containing crev proofs (`*.crev` files), for example crev's local cache
+    /// of proof repositories
+    pub fn crev_proofs(mut self, crev_proofs: impl Into<PathBuf>) -> Self {
+        self.crev_proofs = Some(crev_proofs.into());
+        self
+    }
+
+    /// the crev id whose trust proofs (and the ones of the ids it trusts) give the trust level
+    /// of the reviewers, without it the trust of reviewers is unknown
+    pub fn crev_id(mut self, crev_id: impl Into<String>) -> Self {
+        self.crev_id = Some(crev_id.into());
+        self
+    }
+
+    /// compare the files of each crate published on crates.io with its repository, at the
+    /// commit it was published from (according to its `.cargo_vcs_info.json`). Repositories
+    /// are cloned in the cache folder, unless a `source_mirror` is given.
+    pub fn verify_sources(mut self, verify_sources: bool) -> Self {
+        self.verify_sources = verify_sources;
+        self
+    }
+
+    /// a folder containing clones of the repositories of the dependencies (each clone being
+    /// named after the repository or the crate), used to verify the sources without cloning
+    /// them (this enables `verify_sources`)
+    pub fn source_mirror(mut self, source_mirror: impl Into<PathBuf>) -> Self {
+        self.source_mirror = Some(source_mirror.into());
+        self.verify_sources = true;
+        self
+    }
+
+    /// the target directory in which the dependencies are checked, it is kept between
+    /// analyses so that only the crates that changed are checked again. It should not be
+    /// used by other builds: the analysis builds with its own `RUSTFLAGS` (and with clippy),
+    /// and the two builds would keep invalidating each other.
+    pub fn target_dir(mut self, target_dir: impl Into<PathBuf>) -> Self {
+        self.target_dir = Some(target_dir.into());
+        self
+    }
+
+    /// check the dependencies in the `dephell` folder of the workspace's target directory
+    /// instead of a temporary one (ignored if a `target_dir` is given), see `target_dir`
+    pub fn reuse_target_dir(mut self, reuse_target_dir: bool) -> Self {
+        self.reuse_target_dir = reuse_target_dir;
+        self
+    }
+
+    /// do not check the dependencies (for example when the workspace doesn't build on this
+    /// machine): the lines of code are counted over the module tree of each package, every
+    /// dependency is considered used, and the metrics only known once dependencies are
+    /// compiled (the panic sites and the libraries linked by build scripts) are not collected
+    pub fn no_build(mut self, no_build: bool) -> Self {
+        self.no_build = no_build;
+        self
+    }
+
+    /// a github personnal access token (PAT) used to query the github API
+    /// (github limits queries that are not authenticated)
+    pub fn github_token(mut self, username: impl Into<String>, token: impl Into<String>) -> Self {
+        self.github_token = Some((username.into(), token.into()));
+        self
+    }
+
+    /// a proxy (of the form `PROTOCOL://IP:PORT`) used to make external requests
+    pub fn proxy(mut self, proxy: impl Into<String>) -> Self {
+        self.proxy = Some(proxy.into());
+        self
+    }
+
+    /// where to cache the metrics obtained from crates.io and github
+    /// (by default `~/.cache/cargo-dephell`, `None` disables the cache)
+    pub fn cache_dir(mut self, cache_dir: Option<PathBuf>) -> Self {
+        self.cache_dir = cache_dir;
+        self
+    }
+
+    /// how long the cached metrics are used before being obtained again (by default a day)
+    pub fn cache_ttl(mut self, cache_ttl: Duration) -> Self {
+        self.cache_ttl = cache_ttl;
+        self
+    }
+
+    /// obtain every metric again instead of using the cache (the cache is then updated)
+    pub fn refresh(mut self, refresh: bool) -> Self {
+        self.refresh = refresh;
+        self
+    }
+
+    /// the number of packages analyzed in parallel (by default the number of CPUs)
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// by default the analysis doesn't print anything, this can be used to print its progress
+    pub fn quiet(mut self, quiet: bool) -> Self {
+        self.quiet = quiet;
+        self
+    }
+
+    /// the path to the `Cargo.toml` to analyze
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// the same options, for another `Cargo.toml`
+    pub(crate) fn with_manifest_path(mut self, manifest_path: PathBuf) -> Self {
+        self.manifest_path = manifest_path;
+        self
+    }
+}
+
+/// The version of the report format (see `AnalysisReport::schema_version`).
+/// It is incremented whenever a field is removed or changes meaning,
+/// adding new fields does not change it.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// AnalysisReport is the result of an analysis.
+/// Packages are identified by their name and version (see `package_key`).
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct AnalysisReport {
+    /// the version of the report format (see `SCHEMA_VERSION`)
+    pub schema_version: u32,
+    /// the version of cargo-dephell that generated the report
+    pub tool_version: String,
+    /// when the report was generated
+    pub generated_at: DateTime<Utc>,
+    /// the absolute path of the `Cargo.toml` that was analyzed
+    pub manifest_path: PathBuf,
+    /// the options used for the analysis
+    pub options: AnalysisOptions,
+    /// the workspace crates that were analyzed
+    pub root_crates: BTreeSet<String>,
+    /// the dependencies directly imported by the root crates
+    pub main_dependencies: BTreeSet<String>,
+    /// every dependency of the root crates and of their dependencies (the dependency graph)
+    #[serde(default)]
+    pub dependency_edges: Vec<DependencyEdge>,
+    /// the analysis of every dependency
+    pub analysis_result: BTreeMap<String, PackageRisk>,
+    /// the dependencies that are imported with several versions, indexed by name
+    pub duplicate_versions: BTreeMap<String, Vec<DuplicateVersion>>,
+    /// the metrics (fields of `PackageRisk`) that were not collected, and why
+    /// (the metrics are then `null` for every package, which is different from zero)
+    pub unavailable_metrics: BTreeMap<String, String>,
+}
+
+/// Returns the JSON schema of `AnalysisReport` (what `cargo dephell --json-schema` prints).
+pub fn report_schema() -> RootSchema {
+    schemars::schema_for!(AnalysisReport)
+}
+
+//
+// Analysis
+// ========
+//
+
+/// the metrics obtained via the github API
+const GITHUB_METRICS: [&str; 2] = ["stargazers_count", "active_contributors"];
+
+/// the metrics obtained via the crates.io API
+const CRATES_IO_METRICS: [&str; 3] = [
+    "crates_io_dependent",
+    "crates_io_last_updated",
+    "crates_io_yanked",
+];
+
+/// the clippy lints reporting places that can panic
+const PANIC_LINTS: [&str; 4] = [
+    "clippy::panic",
+    "clippy::unwrap_used",
+    "clippy::expect_used",
+    "clippy::indexing_slicing",
+];
+
+/// the metrics that are only known once the dependencies are compiled
+const BUILD_METRICS: [&str; 4] = [
+    "used",
+    "native_libraries",
+    "panic_sites",
+    "total_panic_sites",
+];
+
+/// the metrics obtained via the audit ledger
+const AUDIT_METRICS: [&str; 3] = ["audit_status", "unaudited_loc", "total_unaudited_loc"];
+
+/// the metrics obtained via cargo-vet
+const VET_METRICS: [&str; 3] = ["vet", "unvetted_loc", "total_unvetted_loc"];
+
+/// Takes some `options` (containing at least a `manifest_path`) and produce an analysis.
 ///
 /// Let's define some useful terms as well:
 /// - **workspace packages** or **root crates**: crates that live in the workspace
@@ -130,21 +794,121 @@ fn create_or_update_dependency(
 ///   at some point. For example if A imports B and B imports C,
 ///   then C is a transitive dependency of A.
 ///
-pub fn analyze_repo(
-    manifest_path: &str,
-    http_client: reqwest::blocking::Client,
-    github_token: Option<(&str, &str)>,
-    packages: Option<Vec<&str>>,
-    to_ignore: Option<Vec<&str>>,
-    quiet: bool,
-) -> Result<
-    (
-        HashSet<String>,              // root_crates
-        HashSet<String>,              // main_dependencies
-        HashMap<String, PackageRisk>, // analysis_result
-    ),
-    String,
-> {
+pub fn analyze_repo(options: &AnalysisOptions) -> Result<AnalysisReport, Error> {
+    let generated_at = Utc::now();
+
+    // the risk weights must be known metrics
+    if let Some(metric) = options
+        .risk_weights
+        .keys()
+        .find(|metric| !risk::is_risk_metric(metric))
+    {
+        return Err(Error::UnknownRiskMetric(metric.clone()));
+    }
+
+    // read the advisory database (if given)
+    let advisory_db = options
+        .advisory_db
+        .as_deref()
+        .map(AdvisoryDatabase::open)
+        .transpose()?;
+
+    // read the audit ledger, the cargo-vet store and the crev proofs (if any)
+    let manifest_dir = options
+        .manifest_path
+        .parent()
+        .unwrap_or_else(|| Path::new(""));
+    let audit_file = options.audit_file.clone().or_else(|| {
+        Some(manifest_dir.join(AUDIT_FILE_NAME)).filter(|audit_file| audit_file.is_file())
+    });
+    let audit_ledger = audit_file
+        .as_deref()
+        .map(AuditLedger::from_file)
+        .transpose()?;
+    let vet_dir = options
+        .vet_dir
+        .clone()
+        .or_else(|| Some(manifest_dir.join(VET_DIR_NAME)).filter(|vet_dir| vet_dir.is_dir()));
+    let vet_store = vet_dir.as_deref().map(VetStore::open).transpose()?;
+    let crev_proofs = options
+        .crev_proofs
+        .as_deref()
+        .map(|crev_proofs| CrevProofs::open(crev_proofs, options.crev_id.as_deref()))
+        .transpose()?;
+
+    // compare the published crates with their repositories (if asked), repositories
+    // are cloned in the cache (or in a temporary folder) unless there is a mirror
+    let clones_tmp_dir = match options.cache_dir {
+        None if options.verify_sources && options.source_mirror.is_none() => {
+            Some(TempDir::new("dephell_repositories")?)
+        }
+        _ => None,
+    };
+    let clone_dir = options
+        .cache_dir
+        .as_ref()
+        .map(|cache_dir| cache_dir.join("repositories"))
+        .or_else(|| clones_tmp_dir.as_ref().map(|dir| dir.path().to_path_buf()));
+    let source_verifier = match (&options.source_mirror, options.offline) {
+        _ if !options.verify_sources => None,
+        (Some(source_mirror), _) => Some(SourceVerifier::new(Some(source_mirror.clone()), None)),
+        (None, false) => Some(SourceVerifier::new(None, clone_dir)),
+        (None, true) => None,
+    };
+
+    // create an HTTP client (used for example to query github API to get # of stars)
+    let mut http_client = reqwest::blocking::ClientBuilder::new().user_agent("mimoo/cargo-dephell");
+    if let Some(proxy) = &options.proxy {
+        http_client = http_client.proxy(reqwest::Proxy::all(proxy)?);
+    }
+    let http_client = http_client.build()?;
+    let cache = Cache::new(
+        options.cache_dir.clone(),
+        options.cache_ttl,
+        options.refresh,
+    );
+    let github_token = options
+        .github_token
+        .as_ref()
+        .filter(|_| !options.offline)
+        .map(|(username, token)| (username.as_str(), token.as_str()));
+
+    // record the metrics that won't be collected
+    let mut unavailable_metrics = BTreeMap::new();
+    if options.offline {
+        for metric in GITHUB_METRICS.iter().chain(CRATES_IO_METRICS.iter()) {
+            unavailable_metrics.insert(metric.to_string(), "offline mode".to_string());
+        }
+    } else if github_token.is_none() {
+        for metric in GITHUB_METRICS.iter() {
+            unavailable_metrics.insert(metric.to_string(), "no github token".to_string());
+        }
+    }
+    if audit_ledger.is_none() {
+        for metric in AUDIT_METRICS.iter() {
+            unavailable_metrics.insert(metric.to_string(), "no audit file".to_string());
+        }
+    }
+    if vet_store.is_none() {
+        for metric in VET_METRICS.iter() {
+            unavailable_metrics.insert(metric.to_string(), "no cargo-vet store".to_string());
+        }
+    }
+    if crev_proofs.is_none() {
+        unavailable_metrics.insert("crev_reviews".to_string(), "no crev proofs".to_string());
+    }
+    if source_verifier.is_none() {
+        let reason = if options.verify_sources {
+            "offline mode"
+        } else {
+            "sources not verified"
+        };
+        unavailable_metrics.insert("source_verification".to_string(), reason.to_string());
+    }
+    if advisory_db.is_none() {
+        unavailable_metrics.insert("advisories".to_string(), "no advisory database".to_string());
+    }
+
     //
     // Obtain package graph via guppy
     // ------------------------------
@@ -152,18 +916,27 @@ pub fn analyze_repo(
 
     // obtain metadata from manifest_path
     let mut cmd = MetadataCommand::new();
-    cmd.manifest_path(manifest_path);
+    cmd.manifest_path(&options.manifest_path);
+    if options.offline {
+        cmd.other_options(vec!["--offline"]);
+    }
 
     // construct graph with guppy
-    let package_graph = PackageGraph::from_command(&mut cmd).map_err(|err| err.to_string())?;
+    let metadata = cmd.exec()?;
+    let workspace_target_dir = if options.reuse_target_dir && options.target_dir.is_none() {
+        Some(workspace_target_dir(&metadata)?)
+    } else {
+        None
+    };
+    let package_graph = metadata.build_graph()?;
 
     // check for dependencies
-    if !quiet {
+    if !options.quiet {
         let cycles = package_graph.cycles().all_cycles();
         for cycle in cycles {
             let cycle: Vec<&str> = cycle
                 .iter()
-                .map(|x| package_graph.metadata(&x).unwrap().name())
+                .map(|x| package_graph.metadata(x).unwrap().name())
                 .collect();
             println!("- dependency cycle detected: {:?}", cycle);
         }
@@ -175,68 +948,66 @@ pub fn analyze_repo(
     // or every crate members of the workspace (if there is a workspace)
     //
 
-    let root_crates = package_graph.workspace().member_ids().map(|x| x.clone());
+    let root_crates = package_graph.workspace().member_ids().cloned();
     let root_crates: HashSet<PackageId> = HashSet::from_iter(root_crates);
-    let mut root_crates_to_analyze: HashSet<PackageId> = root_crates.clone();
+    let mut root_crates_to_analyze: HashSet<PackageId> = root_crates;
     // either select specific packages or remove ignored packages
-    if let Some(packages) = packages {
-        root_crates_to_analyze = root_crates_to_analyze
-            .into_iter()
-            .filter(|pkg_id| {
-                let package_metadata = package_graph.metadata(pkg_id).unwrap();
-                let package_name = package_metadata.name();
-                packages.contains(&package_name)
-            })
-            .collect();
-    } else if let Some(to_ignore) = to_ignore {
-        root_crates_to_analyze = root_crates_to_analyze
-            .into_iter()
-            .filter(|pkg_id| {
-                let package_metadata = package_graph.metadata(pkg_id).unwrap();
-                let package_name = package_metadata.name();
-                !to_ignore.contains(&package_name)
-            })
-            .collect();
+    if let Some(packages) = &options.packages {
+        root_crates_to_analyze.retain(|pkg_id| {
+            let package_metadata = package_graph.metadata(pkg_id).unwrap();
+            let package_name = package_metadata.name();
+            packages.iter().any(|package| package == package_name)
+        });
+    } else if let Some(to_ignore) = &options.to_ignore {
+        root_crates_to_analyze.retain(|pkg_id| {
+            let package_metadata = package_graph.metadata(pkg_id).unwrap();
+            let package_name = package_metadata.name();
+            !to_ignore.iter().any(|ignored| ignored == package_name)
+        });
     }
 
-    if root_crates_to_analyze.len() == 0 {
-        return Err("dephell: no package to analyze was found".to_string());
+    if root_crates_to_analyze.is_empty() {
+        return Err(Error::NoPackageToAnalyze);
     }
 
     // What dependencies do we want to analyze?
     // ----------------------------------------
+    // only the dependencies that get built for the selected features
     //
 
+    let resolved = ResolvedGraph::new(
+        &package_graph,
+        &root_crates_to_analyze,
+        &options.features,
+        &options.targets,
+        options.include_dev,
+    )?;
+
     let mut analysis_result: HashMap<PackageId, PackageRisk> = HashMap::new();
 
-    // TODO: combine the two loops and inline `create_or_update...`
     // find all direct dependencies
-    let mut main_dependencies_ids: HashSet<PackageId> = HashSet::new();
-    let mut main_dependencies: HashSet<String> = HashSet::new();
+    let mut main_dependencies: BTreeSet<String> = BTreeSet::new();
     for root_crate in &root_crates_to_analyze {
         // (non-ignored) root crate > direct dependency
-        let dep_links = package_graph
-            .metadata(root_crate)
-            .unwrap()
-            .direct_links()
-            // ignore dev dependencies
-            .filter(|dep_link| !dep_link.dev_only());
-        for dep_link in dep_links {
-            main_dependencies_ids.insert(dep_link.to().id().to_owned());
-            main_dependencies.insert(dep_link.to().name().to_string());
-            create_or_update_dependency(&mut analysis_result, &dep_link);
+        for dep_link in resolved.direct_links(root_crate) {
+            main_dependencies.insert(package_key(&dep_link.to()));
         }
     }
 
     // find all transitive dependencies
-    let transitive_dependencies = package_graph.query_forward(&main_dependencies_ids).unwrap();
-    // ignore dev dependencies
-    let transitive_dependencies =
-        transitive_dependencies.resolve_with_fn(|_, link| !link.dev_only());
-    let transitive_dependencies = transitive_dependencies.links(DependencyDirection::Reverse);
     // (non-ignored) root crate > direct dependency > transitive dependencies
-    for dep_link in transitive_dependencies {
-        create_or_update_dependency(&mut analysis_result, &dep_link);
+    let mut dependency_edges = Vec::new();
+    for package_id in resolved.package_ids(DependencyDirection::Forward) {
+        for dep_link in resolved.direct_links(package_id) {
+            create_or_update_dependency(&mut analysis_result, dep_link);
+            for kind in DependencyKind::of_link(dep_link) {
+                dependency_edges.push(DependencyEdge {
+                    from: package_key(&dep_link.from()),
+                    to: package_key(&dep_link.to()),
+                    kind,
+                });
+            }
+        }
     }
 
     //
@@ -244,219 +1015,383 @@ pub fn analyze_repo(
     // ---------------------------------------------
     //
 
-    let target_dir = TempDir::new("target_dir").expect("could not create temporary folder");
-    let target_dir = target_dir.path();
-    let output = std::process::Command::new("cargo")
-        .env("RUSTFLAGS", "-Funsafe-code  --cap-lints=warn")
-        .args(&[
-            "check",
-            "-vv",
-            "--message-format=json-diagnostic-rendered-ansi",
-            "--manifest-path",
-            manifest_path,
-            "--target-dir",
-            target_dir.to_str().unwrap(),
-        ])
-        .output()
-        .expect("failed to build crate");
-
-    if !output.status.success() && !quiet {
-        eprintln!("dephell: could not build the target manifest path.");
-        eprintln!("{}", std::str::from_utf8(&output.stderr).unwrap());
-        return Err("Could not build the target manifest path.".to_string());
-    }
-
-    // .unsafe_loc - find unsafe by analyzing the compiler's output
-    let output = std::io::Cursor::new(output.stdout);
-    for message in cargo_metadata::Message::parse_stream(output) {
-        match message {
-            Ok(cargo_metadata::Message::CompilerMessage(msg)) => {
-                if let Some(code) = msg.message.code {
-                    if code.code == "unsafe_code" {
-                        let package_id = PackageId::new(msg.package_id.repr);
-                        analysis_result
-                            .entry(package_id)
-                            .and_modify(|r| r.unsafe_loc += 1);
-                    }
-                }
+    // the dependencies are checked in a temporary target directory, unless one is kept
+    // between analyses (in which case only the crates that changed are checked again),
+    // or not checked at all in `no_build` mode
+    let target_tmp_dir;
+    let target_dir = match (&options.target_dir, workspace_target_dir) {
+        _ if options.no_build => None,
+        (Some(target_dir), _) => Some(std::env::current_dir()?.join(target_dir)),
+        (None, Some(workspace_target_dir)) => Some(workspace_target_dir.join("dephell")),
+        (None, None) => {
+            target_tmp_dir = TempDir::new("target_dir")?;
+            Some(target_tmp_dir.path().to_path_buf())
+        }
+    };
+    let checked = match &target_dir {
+        Some(target_dir) => check_dependencies(
+            options,
+            target_dir,
+            &mut analysis_result,
+            &mut unavailable_metrics,
+        )?,
+        None => {
+            for metric in BUILD_METRICS.iter() {
+                unavailable_metrics.insert(metric.to_string(), "no build".to_string());
             }
-            _ => (),
+            CheckedDependencies::default()
         }
-    }
-
-    // TODO: find # of panic
-
-    /*
-    cargo clippy -vv --message-format=json-diagnostic-rendered-ansi -- -Fclippy::panic --cap-lints=warn 2>/dev/null | egrep '^\{' | jq -r 'select(.message?.code?.code? == "clippy::panic").message?.rendered?'
-    */
+    };
 
     // Analyze!
     // --------
     //
 
-    for (package_id, mut package_risk) in analysis_result.iter_mut() {
-        // .direct_dependencies
-        package_risk.direct_dependencies = package_graph
-            .metadata(package_id)
-            .unwrap()
-            .direct_links()
-            .filter(|dep_link| !dep_link.dev_only())
-            .map(|dep_link| dep_link.to().name().to_string())
-            .collect();
+    // the cfg options of the targets and of the host, to follow the module tree of packages
+    let cfgs = modules::Cfg::of_targets(&options.targets)?;
+    let host_cfg = modules::Cfg::host()?;
 
-        // .transitive_dependencies
-        package_risk.transitive_dependencies = package_graph
-            .query_forward(std::iter::once(package_id))
-            .unwrap()
-            .resolve()
-            .links(DependencyDirection::Forward)
-            .filter(|dep_link| !dep_link.dev_only())
-            .map(|dep_link| dep_link.to().name().to_string())
-            .collect();
+    // packages are analyzed in parallel (this includes file system and network metrics)
+    let thread_pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(options.jobs.unwrap_or(0))
+        .build()?;
+    let github_repo_regex = Regex::new(r"github\.com/([a-zA-Z0-9._-]*/[a-zA-Z0-9._-]*)").unwrap();
+    let dependency_kinds = resolved.dependency_kinds();
+    thread_pool.install(|| {
+        analysis_result.par_iter_mut().try_for_each(
+            |(package_id, package_risk)| -> Result<(), Error> {
+                // .dependency_kinds
+                package_risk.dependency_kinds = dependency_kinds
+                    .get(package_id)
+                    .cloned()
+                    .unwrap_or_default();
 
-        // .root_importers
-        let root_importers =
-            metrics::get_root_importers(&package_graph, &root_crates_to_analyze, package_id);
-        package_risk.root_importers = root_importers;
-
-        // .exclusive_deps_introduced
-        let exclusive_deps_introduced =
-            metrics::get_exclusive_deps(&package_graph, &root_crates_to_analyze, package_id);
-        package_risk.exclusive_deps_introduced = exclusive_deps_introduced;
-
-        // .in_host_target
-        let (used, dependency_files) = metrics::get_dependency_files(
-            &package_risk.name,
-            package_risk.manifest_path.as_path(),
-            &target_dir,
-        );
-        package_risk.used = used;
-
-        // .loc + .rust_loc
-        metrics::get_loc(&mut package_risk, &dependency_files);
-
-        // is this a github repo?
-        if let Some(repo_url) = &package_risk.repo {
-            if let Some(github_token) = github_token {
-                let re = Regex::new(r"github\.com/([a-zA-Z0-9._-]*/[a-zA-Z0-9._-]*)").unwrap();
-                if let Some(repo_name) = re
-                    .captures(repo_url)
-                    .and_then(|caps| caps.get(1))
-                    .map(|m| m.as_str())
-                {
-                    // .stargazers_count
-                    let stars =
-                        metrics::get_github_stars(http_client.clone(), github_token, &repo_name);
-                    package_risk.stargazers_count = stars;
-
-                    // .active_contributors
-                    let active_contributors = metrics::get_active_maintainers(
-                        http_client.clone(),
-                        github_token,
-                        &repo_name,
+                // .direct_dependencies
+                package_risk.direct_dependencies = resolved
+                    .direct_links(package_id)
+                    .map(|dep_link| package_key(&dep_link.to()))
+                    .collect();
+
+                // .transitive_dependencies
+                package_risk.transitive_dependencies = resolved
+                    .transitive_dependencies(package_id)
+                    .into_iter()
+                    .map(|dep_id| package_key(&package_graph.metadata(dep_id).unwrap()))
+                    .collect();
+
+                // .root_importers
+                let root_importers =
+                    metrics::get_root_importers(&resolved, &root_crates_to_analyze, package_id);
+                package_risk.root_importers = root_importers;
+
+                // .exclusive_deps_introduced
+                let exclusive_deps_introduced = metrics::get_exclusive_deps(&resolved, package_id)?;
+                package_risk.exclusive_deps_introduced = exclusive_deps_introduced;
+
+                // the rust files compiled according to the module tree (used when there are
+                // no dep-info files, and to leave out the lines disabled by `#[cfg(...)]`),
+                // with the cfg options set by the build script (unknown without a build)
+                let crate_roots = modules::crate_roots(
+                    &resolved.metadata(package_id),
+                    &package_risk.dependency_kinds,
+                    options.include_dev,
+                );
+                let script_cfg = modules::Cfg::parse(
+                    checked
+                        .script_cfgs
+                        .get(package_id)
+                        .into_iter()
+                        .flatten()
+                        .map(String::as_str),
+                );
+                let module_files = modules::get_module_files(
+                    &crate_roots,
+                    &cfgs,
+                    &host_cfg,
+                    &script_cfg,
+                    &resolved.enabled_features(package_id),
+                );
+
+                // .used (unknown without a build)
+                let (used, dependency_files) = match &target_dir {
+                    Some(target_dir) => {
+                        let rustc_dir = if resolved.metadata(package_id).source().is_path() {
+                            package_graph.workspace().root()
+                        } else {
+                            package_risk.manifest_path.parent().unwrap()
+                        };
+                        let (used, dependency_files) = metrics::get_dependency_files(
+                            &package_risk.name,
+                            package_risk.manifest_path.as_path(),
+                            rustc_dir,
+                            target_dir,
+                            &options.targets,
+                            &checked.dep_info_files,
+                            &module_files,
+                        );
+                        (Some(used), dependency_files)
+                    }
+                    None => (None, module_files.paths()),
+                };
+                package_risk.used = used;
+
+                // .loc + .rust_loc
+                metrics::get_loc(package_risk, &dependency_files, &module_files);
+
+                // .unsafe_loc + .unsafe_metrics
+                metrics::get_unsafe_metrics(package_risk, &dependency_files);
+
+                // .native_loc (native code can only be compiled by a build script)
+                if package_risk.build_script {
+                    metrics::get_native_loc(package_risk, &dependency_files);
+                }
+
+                // .sys_crate
+                package_risk.sys_crate = metrics::is_sys_crate(package_risk);
+
+                // .vcs_commit
+                package_risk.vcs_commit = metrics::get_vcs_info(&package_risk.manifest_path)
+                    .map(|vcs_info| vcs_info.git.sha1);
+
+                // .audits + .audit_status + .unaudited_loc (workspace crates are not audited)
+                if let (Some(audit_ledger), false) = (&audit_ledger, package_risk.internal) {
+                    let audits = audit_ledger.audits_for(
+                        &package_risk.name,
+                        &package_risk.version,
+                        package_risk.vcs_commit.as_deref(),
                     );
-                    package_risk.active_contributors = active_contributors;
+                    let audit_status = AuditStatus::from_audits(&audits);
+                    package_risk.unaudited_loc = match audit_status {
+                        AuditStatus::Audited => Some(0),
+                        _ => Some(package_risk.loc),
+                    };
+                    package_risk.audit_status = Some(audit_status);
+                    package_risk.audits = audits;
+                }
+
+                // .vet + .unvetted_loc + .crev_reviews (workspace crates are not vetted)
+                if let (Some(vet_store), false) = (&vet_store, package_risk.internal) {
+                    let dev_only = package_risk
+                        .dependency_kinds
+                        .iter()
+                        .all(|kind| *kind == DependencyKind::Dev);
+                    let vet = vet_store.status(&package_risk.name, &package_risk.version, dev_only);
+                    package_risk.unvetted_loc = Some(if vet.vetted { 0 } else { package_risk.loc });
+                    package_risk.vet = Some(vet);
+                }
+                if let (Some(crev_proofs), false) = (&crev_proofs, package_risk.internal) {
+                    let reviews =
+                        crev_proofs.reviews_for(&package_risk.name, &package_risk.version);
+                    package_risk.crev_reviews = Some(reviews);
                 }
-            }
-        }
 
-        // .crates_io_dependent
-        // TODO: do not make a request to crates.io if this is not a crates.io dep
-        let crates_io_dependent =
-            metrics::get_crates_io_dependent(http_client.clone(), &package_risk.name);
-        package_risk.crates_io_dependent = crates_io_dependent;
+                // .advisories
+                if let Some(advisory_db) = &advisory_db {
+                    let advisories =
+                        advisory_db.advisories_for(&package_risk.name, &package_risk.version);
+                    package_risk.advisories = Some(advisories);
+                }
 
-        // .crates_io_dependent
-        let crates_io_last_updated =
-            metrics::get_crates_io_last_updated(http_client.clone(), &package_risk.name);
-        package_risk.crates_io_last_updated = crates_io_last_updated;
-    }
+                // .source_verification
+                if let (Some(source_verifier), false) = (&source_verifier, package_risk.internal) {
+                    let source_verification = source_verifier.verify(
+                        &package_risk.name,
+                        package_risk.repo.as_deref(),
+                        &package_risk.manifest_path,
+                    );
+                    package_risk.source_verification = Some(source_verification);
+                }
 
-    // total LOC
-    // ---------
-    // we need to calculate total LOC after the fact
+                // is this a github repo?
+                if let Some(repo_url) = &package_risk.repo {
+                    if let Some(github_token) = github_token {
+                        if let Some(repo_name) = github_repo_regex
+                            .captures(repo_url)
+                            .and_then(|caps| caps.get(1))
+                            .map(|m| m.as_str())
+                        {
+                            // .stargazers_count
+                            let stars = cache.get_or_fetch("stargazers_count", repo_name, || {
+                                metrics::get_github_stars(
+                                    http_client.clone(),
+                                    github_token,
+                                    repo_name,
+                                )
+                            });
+                            package_risk.stargazers_count = stars;
 
-    let transitive_dependencies = package_graph.query_forward(&main_dependencies_ids).unwrap();
-    // ignore dev dependencies
-    let transitive_dependencies =
-        transitive_dependencies.resolve_with_fn(|_, link| !link.dev_only());
-    let transitive_dependencies = transitive_dependencies.package_ids(DependencyDirection::Reverse);
+                            // .active_contributors
+                            let active_contributors =
+                                cache.get_or_fetch("active_contributors", repo_name, || {
+                                    metrics::get_active_maintainers(
+                                        http_client.clone(),
+                                        github_token,
+                                        repo_name,
+                                    )
+                                });
+                            package_risk.active_contributors = active_contributors;
+                        }
+                    }
+                }
 
-    'main_loop: for package_id in transitive_dependencies {
-        // already calculated
-        if analysis_result[&package_id].total_calculated {
-            continue;
-        }
+                // no crates.io request in offline mode
+                if options.offline {
+                    return Ok(());
+                }
 
-        // get direct deps
-        let direct_deps: Vec<_> = package_graph
-            .metadata(package_id)
-            .unwrap()
-            .direct_links()
-            .filter(|dep_link| !dep_link.dev_only())
-            .map(|dep_link| dep_link.to().id())
-            .collect();
+                // crates.io metrics are cached per name and version
+                let crate_key = format!("{}/{}", package_risk.name, package_risk.version);
 
-        // easy, no deps
-        if direct_deps.len() == 0 {
-            let loc = analysis_result[&package_id].loc;
-            let rust_loc = analysis_result[&package_id].rust_loc;
-            let unsafe_loc = analysis_result[&package_id].unsafe_loc;
-            let package_risk = analysis_result.get_mut(&package_id).unwrap();
-            package_risk.total_loc = loc;
-            package_risk.total_rust_loc = rust_loc;
-            package_risk.total_unsafe_loc = unsafe_loc;
-            package_risk.total_calculated = true;
-            // next
-            continue;
-        }
+                // .crates_io_dependent
+                // TODO: do not make a request to crates.io if this is not a crates.io dep
+                let crates_io_dependent =
+                    cache.get_or_fetch("crates_io_dependent", &crate_key, || {
+                        metrics::get_crates_io_dependent(http_client.clone(), &package_risk.name)
+                    });
+                package_risk.crates_io_dependent = crates_io_dependent;
 
-        // otherwise compute from direct dependencies total count
-        let mut total_loc = 0;
-        let mut total_rust_loc = 0;
-        let mut total_unsafe_loc = 0;
-
-        for direct_dep_id in direct_deps {
-            let direct_dep = &analysis_result[direct_dep_id];
-            if !direct_dep.total_calculated {
-                println!(
-                    "total loc error: {:?} was not calculated due to {:?} not being calculated",
-                    analysis_result[&package_id].name, direct_dep.name
-                );
-                continue 'main_loop;
-            }
+                // .crates_io_last_updated
+                let crates_io_last_updated =
+                    cache.get_or_fetch("crates_io_last_updated", &crate_key, || {
+                        metrics::get_crates_io_last_updated(http_client.clone(), &package_risk.name)
+                    });
+                package_risk.crates_io_last_updated = crates_io_last_updated;
 
-            total_loc += direct_dep.total_loc;
-            total_rust_loc += direct_dep.total_rust_loc;
-            total_unsafe_loc += direct_dep.total_unsafe_loc;
-        }
+                // .crates_io_yanked
+                let crates_io_yanked = cache.get_or_fetch("crates_io_yanked", &crate_key, || {
+                    metrics::get_crates_io_yanked(
+                        http_client.clone(),
+                        &package_risk.name,
+                        &package_risk.version,
+                    )
+                });
+                package_risk.crates_io_yanked = crates_io_yanked;
+
+                Ok(())
+            },
+        )
+    })?;
 
-        // set
-        let package_risk = analysis_result.get_mut(&package_id).unwrap();
-        package_risk.total_loc = total_loc;
-        package_risk.total_rust_loc = total_rust_loc;
-        package_risk.total_unsafe_loc = total_unsafe_loc;
-        package_risk.total_calculated = true;
+    // duplicate versions
+    // ------------------
+    // find dependencies that are pulled with several versions,
+    // and how each version ends up being pulled
 
-        // next
+    let mut versions_per_name: HashMap<&str, Vec<&PackageId>> = HashMap::new();
+    for (package_id, package_risk) in &analysis_result {
+        versions_per_name
+            .entry(&package_risk.name)
+            .or_default()
+            .push(package_id);
+    }
+    let mut duplicate_versions: BTreeMap<String, Vec<DuplicateVersion>> = BTreeMap::new();
+    for (name, mut package_ids) in versions_per_name {
+        if package_ids.len() < 2 {
+            continue;
+        }
+        package_ids.sort_by_key(|package_id| package_graph.metadata(package_id).unwrap().version());
+        let versions: Vec<DuplicateVersion> = package_ids
+            .into_iter()
+            .map(|package_id| {
+                let package_risk = &analysis_result[package_id];
+                let paths =
+                    metrics::get_import_paths(&resolved, &root_crates_to_analyze, package_id);
+                DuplicateVersion {
+                    version: package_risk.version.clone(),
+                    package: package_key(&package_graph.metadata(package_id).unwrap()),
+                    root_importers: package_risk.root_importers.clone(),
+                    paths,
+                }
+            })
+            .collect();
+        duplicate_versions.insert(name.to_string(), versions);
     }
 
-    // PackageId -> name
-    // -----------------
+    // PackageId -> key
+    // ----------------
     // this is useful because PackageIds are long strings,
-    // and we only care about package names for the result
-    let root_crates_to_analyze: HashSet<String> = root_crates_to_analyze
+    // and we only care about package names and versions for the result
+    let root_crates_to_analyze: BTreeSet<String> = root_crates_to_analyze
         .iter()
         .map(|pkg_id| {
             let package_metadata = package_graph.metadata(pkg_id).unwrap();
             package_metadata.name().to_owned()
         })
         .collect();
-    let analysis_result: HashMap<String, PackageRisk> = analysis_result
+    let mut analysis_result: BTreeMap<String, PackageRisk> = analysis_result
+        .into_iter()
+        .map(|(package_id, package_risk)| {
+            let package_metadata = package_graph.metadata(&package_id).unwrap();
+            (package_key(&package_metadata), package_risk)
+        })
+        .collect();
+
+    // totals
+    // ------
+    // a package and its transitive dependencies, each counted once
+
+    let totals: Vec<(String, PackageRisk)> = analysis_result
         .iter()
-        .map(|(_, package_risk)| (package_risk.name.clone(), package_risk.clone()))
+        .map(|(key, package_risk)| {
+            let total = |metric: fn(&PackageRisk) -> Option<u64>| {
+                transitive_total(&analysis_result, package_risk, metric)
+            };
+            let mut total_unsafe_metrics = package_risk.unsafe_metrics.clone();
+            for dep in &package_risk.transitive_dependencies {
+                if let Some(dep) = analysis_result.get(dep) {
+                    total_unsafe_metrics.add(&dep.unsafe_metrics);
+                }
+            }
+            let totals = PackageRisk {
+                total_loc: total(|p| Some(p.loc)).unwrap_or_default(),
+                total_rust_loc: total(|p| Some(p.rust_loc)).unwrap_or_default(),
+                total_unsafe_loc: total(|p| Some(p.unsafe_loc)).unwrap_or_default(),
+                total_unsafe_metrics,
+                total_native_loc: total(|p| Some(p.native_loc)).unwrap_or_default(),
+                total_panic_sites: total(|p| p.panic_sites),
+                total_unaudited_loc: total(|p| p.unaudited_loc),
+                total_unvetted_loc: total(|p| p.unvetted_loc),
+                ..Default::default()
+            };
+            (key.clone(), totals)
+        })
         .collect();
+    for (key, totals) in totals {
+        let package_risk = analysis_result.get_mut(&key).unwrap();
+        package_risk.total_loc = totals.total_loc;
+        package_risk.total_rust_loc = totals.total_rust_loc;
+        package_risk.total_unsafe_loc = totals.total_unsafe_loc;
+        package_risk.total_unsafe_metrics = totals.total_unsafe_metrics;
+        package_risk.total_native_loc = totals.total_native_loc;
+        package_risk.total_panic_sites = totals.total_panic_sites;
+        package_risk.total_unaudited_loc = totals.total_unaudited_loc;
+        package_risk.total_unvetted_loc = totals.total_unvetted_loc;
+    }
+
+    // risk score
+    // ----------
+    // computed last, as it relies on every other metric
+
+    let today = generated_at.date().naive_utc();
+    for package_risk in analysis_result.values_mut() {
+        package_risk.risk = risk::risk_score(package_risk, &options.risk_weights, today);
+    }
 
     //
-    Ok((root_crates_to_analyze, main_dependencies, analysis_result))
+    Ok(AnalysisReport {
+        schema_version: SCHEMA_VERSION,
+        tool_version: env!("CARGO_PKG_VERSION").to_string(),
+        generated_at,
+        manifest_path: options
+            .manifest_path
+            .canonicalize()
+            .unwrap_or_else(|_| options.manifest_path.clone()),
+        options: options.clone(),
+        root_crates: root_crates_to_analyze,
+        main_dependencies,
+        dependency_edges,
+        analysis_result,
+        duplicate_versions,
+        unavailable_metrics,
+    })
 }
@@ -0,0 +1,157 @@
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+use crate::Error;
+
+/// the name of the audit ledger, looked up next to the analyzed `Cargo.toml`
+pub const AUDIT_FILE_NAME: &str = "AUDIT.toml";
+
+/// AuditScope is how much of a crate was reviewed.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum AuditScope {
+    /// the whole crate was reviewed
+    #[default]
+    Full,
+    /// only some parts of the crate were reviewed (see the notes)
+    Partial,
+}
+
+/// Audit records that someone reviewed a version (or a commit) of a crate.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct Audit {
+    /// the name of the crate
+    #[serde(rename = "crate")]
+    pub crate_name: String,
+    /// the version reviewed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+    /// the commit of the crate's repository that was reviewed (it can be abbreviated),
+    /// matched against the commit a version was published from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub commit: Option<String>,
+    /// who reviewed it
+    pub reviewer: String,
+    /// when it was reviewed
+    #[serde(deserialize_with = "deserialize_date")]
+    pub date: NaiveDate,
+    /// how much of the crate was reviewed
+    #[serde(default)]
+    pub scope: AuditScope,
+    /// anything worth knowing about the review
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub notes: Option<String>,
+}
+
+/// AuditStatus is how much of a version of a crate was reviewed, according to the audit ledger.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum AuditStatus {
+    /// at least one audit covers the whole crate
+    Audited,
+    /// only audits of some parts of the crate were recorded
+    PartiallyAudited,
+    /// no audit was recorded
+    Unaudited,
+}
+
+impl AuditStatus {
+    /// the status of a crate given its audits
+    pub fn from_audits(audits: &[Audit]) -> Self {
+        if audits.iter().any(|audit| audit.scope == AuditScope::Full) {
+            AuditStatus::Audited
+        } else if audits.is_empty() {
+            AuditStatus::Unaudited
+        } else {
+            AuditStatus::PartiallyAudited
+        }
+    }
+}
+
+/// AuditLedger is the list of audits recorded in an `AUDIT.toml` file:
+///
+/// ```toml
+/// [[audit]]
+/// crate = "itoa"
+/// version = "1.0.1"
+/// reviewer = "David Wong"
+/// date = "2021-03-01"
+/// scope = "partial"
+/// notes = "only the unsafe code"
+/// ```
+#[derive(Debug, Default, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuditLedger {
+    #[serde(default, rename = "audit")]
+    pub audits: Vec<Audit>,
+}
+
+impl AuditLedger {
+    /// reads an audit ledger (an `AUDIT.toml`)
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let ledger: Self = toml::from_str(&content)
+            .map_err(|err| Error::AuditFile(path.to_path_buf(), err.to_string()))?;
+        if let Some(audit) = ledger
+            .audits
+            .iter()
+            .find(|audit| audit.version.is_none() && audit.commit.is_none())
+        {
+            return Err(Error::AuditFile(
+                path.to_path_buf(),
+                format!("the audit of {} has no version or commit", audit.crate_name),
+            ));
+        }
+        Ok(ledger)
+    }
+
+    /// the audits of a version of a crate, `commit` being the commit it was published from
+    pub fn audits_for(&self, name: &str, version: &str, commit: Option<&str>) -> Vec<Audit> {
+        self.audits
+            .iter()
+            .filter(|audit| audit.crate_name == name)
+            .filter(|audit| {
+                let same_version = audit.version.as_deref() == Some(version);
+                let same_commit = match (audit.commit.as_deref(), commit) {
+                    (Some(audited), Some(commit)) => {
+                        !audited.is_empty() && commit.starts_with(audited)
+                    }
+                    _ => false,
+                };
+                same_version || same_commit
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// appends an audit at the end of an audit ledger (the file is created if needed)
+    pub fn append(path: &Path, audit: &Audit) -> Result<(), Error> {
+        #[derive(Serialize)]
+        struct Entry<'a> {
+            audit: [&'a Audit; 1],
+        }
+        let entry = toml::to_string(&Entry { audit: [audit] })
+            .map_err(|err| Error::AuditFile(path.to_path_buf(), err.to_string()))?;
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if file.metadata()?.len() > 0 {
+            writeln!(file)?;
+        }
+        file.write_all(entry.as_bytes())?;
+        Ok(())
+    }
+}
+
+/// dates can be written as TOML dates (`2021-03-01`) or strings (`"2021-03-01"`)
+fn deserialize_date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<NaiveDate, D::Error> {
+    let date = match toml::Value::deserialize(deserializer)? {
+        toml::Value::String(date) => date,
+        toml::Value::Datetime(date) => date.to_string(),
+        other => return Err(serde::de::Error::custom(format!("invalid date {}", other))),
+    };
+    date.parse().map_err(serde::de::Error::custom)
+}
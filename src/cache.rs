@@ -0,0 +1,119 @@
+use chrono::{DateTime, Utc};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// used to create unique temporary files when writing entries
+static TMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// An entry of the cache, stored as JSON in its own file.
+#[derive(Serialize, Deserialize)]
+struct Entry<T> {
+    fetched_at: DateTime<Utc>,
+    value: T,
+}
+
+/// Cache keeps the metrics obtained via the network (crates.io and github) on disk.
+/// Each metric is stored at `<dir>/<metric>/<key>.json`, where the key is the
+/// crate name and version (`name/version`) or the github repository (`owner/repo`).
+pub struct Cache {
+    /// where the cache lives (no cache if `None`)
+    dir: Option<PathBuf>,
+    /// how long an entry stays valid
+    ttl: Duration,
+    /// ignore existing entries (they get overwritten)
+    refresh: bool,
+}
+
+impl Cache {
+    pub fn new(dir: Option<PathBuf>, ttl: Duration, refresh: bool) -> Self {
+        Self { dir, ttl, refresh }
+    }
+
+    /// the default location of the cache:
+    /// `$XDG_CACHE_HOME/cargo-dephell` or `~/.cache/cargo-dephell`
+    pub fn default_dir() -> Option<PathBuf> {
+        let cache_home = std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))?;
+        Some(cache_home.join("cargo-dephell"))
+    }
+
+    /// returns the cached value of `metric` for `key`, or obtains it with `fetch`
+    /// (only values that could be obtained are cached)
+    pub fn get_or_fetch<T: Serialize + DeserializeOwned>(
+        &self,
+        metric: &str,
+        key: &str,
+        fetch: impl FnOnce() -> Option<T>,
+    ) -> Option<T> {
+        let path = match self.path(metric, key) {
+            Some(path) => path,
+            None => return fetch(),
+        };
+        if !self.refresh {
+            if let Some(value) = self.get(&path) {
+                return Some(value);
+            }
+        }
+        let value = fetch()?;
+        if let Err(err) = self.insert(&path, &value) {
+            eprintln!(
+                "dephell: could not write to cache {}: {}",
+                path.display(),
+                err
+            );
+        }
+        Some(value)
+    }
+
+    /// the file of an entry, if there is a cache and the key can be used as a relative path
+    fn path(&self, metric: &str, key: &str) -> Option<PathBuf> {
+        let dir = self.dir.as_ref()?;
+        let key = Path::new(key);
+        if !key
+            .components()
+            .all(|component| matches!(component, Component::Normal(_)))
+        {
+            return None;
+        }
+        let mut path = dir.join(metric).join(key).into_os_string();
+        path.push(".json");
+        Some(path.into())
+    }
+
+    /// reads an entry, if it exists and has not expired
+    fn get<T: DeserializeOwned>(&self, path: &Path) -> Option<T> {
+        let content = fs::read(path).ok()?;
+        let entry: Entry<T> = serde_json::from_slice(&content).ok()?;
+        let age = Utc::now().signed_duration_since(entry.fetched_at).to_std();
+        // (an entry from the future has a negative age)
+        match age {
+            Ok(age) if age > self.ttl => None,
+            _ => Some(entry.value),
+        }
+    }
+
+    /// writes an entry
+    /// (via a temporary file, as the same entry can be written by several threads or processes)
+    fn insert<T: Serialize>(&self, path: &Path, value: &T) -> std::io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let entry = Entry {
+            fetched_at: Utc::now(),
+            value,
+        };
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(format!(
+            ".{}-{}.tmp",
+            std::process::id(),
+            TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp_path, serde_json::to_vec(&entry)?)?;
+        fs::rename(&tmp_path, path)
+    }
+}
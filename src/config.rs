@@ -0,0 +1,203 @@
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::Error;
+
+/// the name of the configuration file, looked up next to the analyzed `Cargo.toml`
+pub const CONFIG_FILE_NAME: &str = "dephell.toml";
+
+/// Config holds persistent settings, read from a `dephell.toml` file
+/// or from the `[package.metadata.dephell]` or `[workspace.metadata.dephell]`
+/// section of a `Cargo.toml`. Every setting is optional, and the command-line
+/// flags take precedence over them:
+///
+/// ```toml
+/// ignore = ["some-workspace-crate"]
+/// features = ["serde"]
+/// targets = ["x86_64-unknown-linux-gnu"]
+///
+/// [output]
+/// html = "dephell.html"
+///
+/// [policy]
+/// max-total-unsafe-loc = 1000
+/// ```
+#[derive(Default, Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct Config {
+    /// the workspace crates to analyze
+    pub packages: Option<Vec<String>>,
+    /// the workspace crates not to analyze
+    pub ignore: Option<Vec<String>>,
+    /// the features to activate
+    pub features: Option<Vec<String>>,
+    /// activate all the features of the workspace crates
+    pub all_features: Option<bool>,
+    /// do not activate the `default` feature of the workspace crates
+    pub no_default_features: Option<bool>,
+    /// the target triples to analyze
+    pub targets: Option<Vec<String>>,
+    /// also analyze the dev dependencies of the workspace crates
+    pub include_dev: Option<bool>,
+    /// do not access the network
+    pub offline: Option<bool>,
+    /// a proxy used to make external requests
+    pub proxy: Option<String>,
+    /// the number of packages analyzed in parallel
+    pub jobs: Option<usize>,
+    /// where to cache network responses (relative to the configuration file)
+    pub cache_dir: Option<PathBuf>,
+    /// how long cached network responses are used, in hours
+    pub cache_ttl: Option<u64>,
+    /// the target directory in which dependencies are checked (relative to the configuration file)
+    pub target_dir: Option<PathBuf>,
+    /// check the dependencies in the workspace's target directory
+    pub reuse_target_dir: Option<bool>,
+    /// do not check the dependencies
+    pub no_build: Option<bool>,
+    /// a local checkout of the RustSec advisory database (relative to the configuration file)
+    pub advisory_db: Option<PathBuf>,
+    /// the audit ledger (relative to the configuration file)
+    pub audit_file: Option<PathBuf>,
+    /// the cargo-vet store (relative to the configuration file)
+    pub vet_dir: Option<PathBuf>,
+    /// a folder containing crev proofs (relative to the configuration file)
+    pub crev_proofs: Option<PathBuf>,
+    /// the crev id whose web of trust gives the trust level of reviewers
+    pub crev_id: Option<String>,
+    /// compare the published crates with their repositories
+    pub verify_sources: Option<bool>,
+    /// a folder containing clones of the repositories of the dependencies
+    /// (relative to the configuration file)
+    pub source_mirror: Option<PathBuf>,
+    /// output settings
+    pub output: OutputConfig,
+    /// the weight of each metric in the risk score, indexed by metric
+    pub risk_weights: BTreeMap<String, f64>,
+    /// the thresholds enforced by the policy check
+    pub policy: PolicyConfig,
+}
+
+/// OutputConfig configures how the report is written.
+#[derive(Default, Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct OutputConfig {
+    /// write the report as HTML to this file (relative to the configuration file)
+    pub html: Option<PathBuf>,
+    /// suppress any output to stdout
+    pub quiet: Option<bool>,
+}
+
+/// PolicyConfig holds the thresholds that dependencies must respect.
+#[derive(Default, Debug, Clone, Deserialize)]
+#[serde(default, rename_all = "kebab-case", deny_unknown_fields)]
+pub struct PolicyConfig {
+    /// maximum number of lines of unsafe code of a dependency (including its own dependencies)
+    pub max_total_unsafe_loc: Option<u64>,
+    /// maximum number of transitive dependencies pulled by a direct dependency
+    pub max_transitive_deps: Option<usize>,
+    /// minimum number of crates.io crates depending on a dependency
+    pub min_crates_io_dependent: Option<u64>,
+    /// maximum number of days since a dependency was last updated on crates.io
+    pub max_days_since_update: Option<i64>,
+    /// fail if a dependency is imported with several versions
+    pub forbid_duplicate_versions: Option<bool>,
+    /// fail if a dependency is affected by a vulnerability or an unsoundness advisory
+    pub forbid_advisories: Option<bool>,
+    /// fail if a dependency is flagged as unmaintained by an advisory
+    pub forbid_unmaintained: Option<bool>,
+    /// fail if a dependency is yanked from crates.io
+    pub forbid_yanked: Option<bool>,
+}
+
+impl PolicyConfig {
+    /// is there no rule to check?
+    pub fn is_empty(&self) -> bool {
+        self.max_total_unsafe_loc.is_none()
+            && self.max_transitive_deps.is_none()
+            && self.min_crates_io_dependent.is_none()
+            && self.max_days_since_update.is_none()
+            && !self.forbid_duplicate_versions.unwrap_or(false)
+            && !self.forbid_advisories.unwrap_or(false)
+            && !self.forbid_unmaintained.unwrap_or(false)
+            && !self.forbid_yanked.unwrap_or(false)
+    }
+}
+
+impl Config {
+    /// reads a configuration file (a `dephell.toml`)
+    pub fn from_file(path: &Path) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)?;
+        let mut config: Self = toml::from_str(&content)
+            .map_err(|err| Error::Config(path.to_path_buf(), err.to_string()))?;
+        config.resolve_paths(path);
+        Ok(config)
+    }
+
+    /// finds the configuration of the crate or workspace at `manifest_path`:
+    /// a `dephell.toml` in the same folder, or the `[package.metadata.dephell]`
+    /// or `[workspace.metadata.dephell]` section of the manifest (in that order).
+    /// Returns the path where the configuration was found, if any.
+    pub fn discover(manifest_path: &Path) -> Result<Option<(PathBuf, Self)>, Error> {
+        let folder = manifest_path.parent().unwrap_or_else(|| Path::new(""));
+        let config_path = folder.join(CONFIG_FILE_NAME);
+        if config_path.is_file() {
+            let config = Self::from_file(&config_path)?;
+            return Ok(Some((config_path, config)));
+        }
+
+        // look for a metadata section in Cargo.toml
+        let manifest = fs::read_to_string(manifest_path)?;
+        let manifest: toml::Value = toml::from_str(&manifest)
+            .map_err(|err| Error::Config(manifest_path.to_path_buf(), err.to_string()))?;
+        for section in &["package", "workspace"] {
+            let metadata = manifest
+                .get(section)
+                .and_then(|section| section.get("metadata"))
+                .and_then(|metadata| metadata.get("dephell"));
+            if let Some(metadata) = metadata {
+                let mut config: Self = metadata.clone().try_into().map_err(|err| {
+                    Error::Config(
+                        manifest_path.to_path_buf(),
+                        format!("[{}.metadata.dephell]: {}", section, err),
+                    )
+                })?;
+                config.resolve_paths(manifest_path);
+                return Ok(Some((manifest_path.to_path_buf(), config)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// paths are relative to the file containing the configuration
+    fn resolve_paths(&mut self, config_path: &Path) {
+        let folder = config_path.parent().unwrap_or_else(|| Path::new(""));
+        if let Some(cache_dir) = &mut self.cache_dir {
+            *cache_dir = folder.join(&cache_dir);
+        }
+        if let Some(target_dir) = &mut self.target_dir {
+            *target_dir = folder.join(&target_dir);
+        }
+        if let Some(advisory_db) = &mut self.advisory_db {
+            *advisory_db = folder.join(&advisory_db);
+        }
+        if let Some(audit_file) = &mut self.audit_file {
+            *audit_file = folder.join(&audit_file);
+        }
+        if let Some(vet_dir) = &mut self.vet_dir {
+            *vet_dir = folder.join(&vet_dir);
+        }
+        if let Some(crev_proofs) = &mut self.crev_proofs {
+            *crev_proofs = folder.join(&crev_proofs);
+        }
+        if let Some(source_mirror) = &mut self.source_mirror {
+            *source_mirror = folder.join(&source_mirror);
+        }
+        if let Some(html) = &mut self.output.html {
+            *html = folder.join(&html);
+        }
+    }
+}
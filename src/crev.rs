@@ -0,0 +1,420 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::Error;
+
+/// CrevReview is a crev review of a version of a dependency
+/// (see https://github.com/crev-dev/cargo-crev).
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct CrevReview {
+    /// the crev id of the reviewer
+    pub from: String,
+    /// the proof repository of the reviewer, if known
+    pub url: Option<String>,
+    /// when the review was made
+    pub date: String,
+    /// how thorough the review was (`none`, `low`, `medium` or `high`)
+    pub thoroughness: String,
+    /// how well the reviewer understood the code (`none`, `low`, `medium` or `high`)
+    pub understanding: String,
+    /// the verdict (`dangerous`, `negative`, `neutral`, `positive` or `strong`)
+    pub rating: String,
+    /// the comment of the reviewer, if any
+    pub comment: Option<String>,
+    /// the trust level given to the reviewer by the web of trust of the configured crev id
+    /// (`distrust`, `none`, `low`, `medium` or `high`), if the reviewer is part of it
+    pub trust: Option<String>,
+}
+
+/// the trust levels of crev, from the lowest to the highest
+const TRUST_LEVELS: [&str; 5] = ["distrust", "none", "low", "medium", "high"];
+
+/// CrevProofs holds the package reviews and trust proofs found in crev proof repositories.
+#[derive(Default)]
+pub struct CrevProofs {
+    /// (crate name, version) -> reviews
+    reviews: HashMap<(String, String), Vec<CrevReview>>,
+}
+
+impl CrevProofs {
+    /// reads every proof (`*.crev` files) found in a folder, for example a clone of
+    /// a crev proof repository or crev's local cache of them.
+    /// Reviewers are only trusted through the trust proofs of `root_id` (and of the ids it
+    /// trusts), note that the signatures of the proofs are not checked.
+    pub fn open(path: &Path, root_id: Option<&str>) -> Result<Self, Error> {
+        if !path.is_dir() {
+            return Err(Error::Crev(path.to_path_buf(), "not a folder".to_string()));
+        }
+
+        // parse every proof
+        let mut proofs = Vec::new();
+        let files = ignore::WalkBuilder::new(path)
+            .standard_filters(false)
+            .build();
+        for file in files {
+            let file = file.map_err(|err| Error::Crev(path.to_path_buf(), err.to_string()))?;
+            if file
+                .path()
+                .extension()
+                .and_then(|extension| extension.to_str())
+                != Some("crev")
+            {
+                continue;
+            }
+            let content = fs::read_to_string(file.path())?;
+            proofs.extend(parse_proofs(&content));
+        }
+
+        // the trust given to each id (none without a root id)
+        let trust = root_id
+            .map(|root_id| web_of_trust(&proofs, root_id))
+            .unwrap_or_default();
+
+        // package reviews of crates.io crates
+        let mut crev_proofs = Self::default();
+        for proof in proofs
+            .iter()
+            .filter(|proof| proof.get("kind") == Some("package review"))
+            .filter(|proof| proof.get("package.source") == Some("https://crates.io"))
+        {
+            let (name, version, from) = match (
+                proof.get("package.name"),
+                proof.get("package.version"),
+                proof.get("from.id"),
+            ) {
+                (Some(name), Some(version), Some(from)) => (name, version, from),
+                _ => continue,
+            };
+            let review = CrevReview {
+                from: from.to_string(),
+                url: proof.get("from.url").map(String::from),
+                date: proof.get("date").unwrap_or_default().to_string(),
+                thoroughness: proof
+                    .get("review.thoroughness")
+                    .unwrap_or("none")
+                    .to_string(),
+                understanding: proof
+                    .get("review.understanding")
+                    .unwrap_or("none")
+                    .to_string(),
+                rating: proof.get("review.rating").unwrap_or("neutral").to_string(),
+                comment: proof
+                    .get("comment")
+                    .filter(|comment| !comment.is_empty())
+                    .map(String::from),
+                trust: trust
+                    .get(from)
+                    .map(|level| TRUST_LEVELS[*level].to_string()),
+            };
+            crev_proofs
+                .reviews
+                .entry((name.to_string(), version.to_string()))
+                .or_default()
+                .push(review);
+        }
+        Ok(crev_proofs)
+    }
+
+    /// the reviews of a version of a crate
+    pub fn reviews_for(&self, name: &str, version: &str) -> Vec<CrevReview> {
+        self.reviews
+            .get(&(name.to_string(), version.to_string()))
+            .cloned()
+            .unwrap_or_default()
+    }
+}
+
+/// the trust given to each id by the web of trust of `root_id`: trust proofs are only followed
+/// from the root id and the ids it trusts (transitively, with at least a low trust).
+/// An id gets the lowest level of a chain of trust proofs leading to it (the highest one if
+/// there are several chains), and distrust from any trusted id wins.
+fn web_of_trust<'a>(proofs: &'a [Proof], root_id: &'a str) -> HashMap<&'a str, usize> {
+    const DISTRUST: usize = 0;
+    const LOW: usize = 2;
+    const HIGH: usize = TRUST_LEVELS.len() - 1;
+
+    // from -> (id, level)
+    let mut edges: HashMap<&str, Vec<(&str, usize)>> = HashMap::new();
+    for proof in proofs
+        .iter()
+        .filter(|proof| proof.get("kind") == Some("trust"))
+    {
+        let level = proof
+            .get("trust")
+            .and_then(|level| TRUST_LEVELS.iter().position(|l| *l == level));
+        if let (Some(from), Some(level)) = (proof.get("from.id"), level) {
+            for id in proof.get_all("ids.id") {
+                edges.entry(from).or_default().push((id, level));
+            }
+        }
+    }
+
+    // distrusted ids are left out of the web of trust, and leaving them out
+    // can't add new distrust: the set of distrusted ids only grows
+    let mut distrusted: HashSet<&str> = HashSet::new();
+    loop {
+        let mut trust: HashMap<&str, usize> = HashMap::new();
+        trust.insert(root_id, HIGH);
+        let mut to_visit = vec![root_id];
+        let mut new_distrusted = Vec::new();
+        while let Some(from) = to_visit.pop() {
+            let from_level = trust[from];
+            if from_level < LOW {
+                continue;
+            }
+            for &(id, level) in edges.get(from).into_iter().flatten() {
+                if id == root_id {
+                    continue;
+                }
+                if level == DISTRUST {
+                    new_distrusted.push(id);
+                    continue;
+                }
+                if distrusted.contains(id) {
+                    continue;
+                }
+                let level = level.min(from_level);
+                if trust.get(id).is_none_or(|current| level > *current) {
+                    trust.insert(id, level);
+                    to_visit.push(id);
+                }
+            }
+        }
+        let len = distrusted.len();
+        distrusted.extend(new_distrusted);
+        if distrusted.len() == len {
+            trust.extend(distrusted.into_iter().map(|id| (id, DISTRUST)));
+            return trust;
+        }
+    }
+}
+
+/// a proof, as a list of (path, value), where the path is made of the keys
+/// leading to the value (for example `review.rating`, or `ids.id` for every id of a list)
+struct Proof(Vec<(String, String)>);
+
+impl Proof {
+    fn get(&self, path: &str) -> Option<&str> {
+        self.0
+            .iter()
+            .find(|(p, _)| p == path)
+            .map(|(_, value)| value.as_str())
+    }
+
+    fn get_all<'a>(&'a self, path: &'a str) -> impl Iterator<Item = &'a str> {
+        self.0
+            .iter()
+            .filter(move |(p, _)| p == path)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// parses the proofs of a file (crev proofs are a simple subset of YAML, signed)
+fn parse_proofs(content: &str) -> Vec<Proof> {
+    content
+        .split("----- BEGIN CREV PROOF -----")
+        .skip(1)
+        .filter_map(|proof| proof.split("----- BEGIN CREV PROOF SIGNATURE -----").next())
+        .map(parse_proof)
+        .collect()
+}
+
+fn parse_proof(body: &str) -> Proof {
+    let mut values = Vec::new();
+    // the keys leading to the current line, with their indentation
+    let mut parents: Vec<(usize, String)> = Vec::new();
+    let mut lines = body.lines().peekable();
+    while let Some(line) = lines.next() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        // list items are indented like map entries
+        let mut indent = line.len() - line.trim_start().len();
+        let mut entry = line.trim_start();
+        if let Some(item) = entry.strip_prefix("- ") {
+            indent += 2;
+            entry = item;
+        }
+        let (key, value) = match entry.split_once(':') {
+            Some((key, value)) => (key.trim(), value.trim()),
+            None => continue,
+        };
+        while parents.last().is_some_and(|(i, _)| *i >= indent) {
+            parents.pop();
+        }
+        let path: Vec<&str> = parents
+            .iter()
+            .map(|(_, parent)| parent.as_str())
+            .chain(std::iter::once(key))
+            .collect();
+        let path = path.join(".");
+
+        if value.is_empty() {
+            parents.push((indent, key.to_string()));
+        } else if value.starts_with('|') || value.starts_with('>') {
+            // block scalar: the following lines that are more indented
+            let mut text = Vec::new();
+            while let Some(next) = lines.peek() {
+                let next_indent = next.len() - next.trim_start().len();
+                if !next.trim().is_empty() && next_indent <= indent {
+                    break;
+                }
+                text.push(next.trim());
+                lines.next();
+            }
+            values.push((path, text.join("\n").trim().to_string()));
+        } else {
+            values.push((path, value.trim_matches('"').trim_matches('\'').to_string()));
+        }
+    }
+    Proof(values)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// a trust proof of `from` for `id`
+    fn trust_proof(from: &str, id: &str, trust: &str) -> String {
+        format!(
+            "----- BEGIN CREV PROOF -----\n\
+             kind: trust\n\
+             version: -1\n\
+             date: \"2021-03-01T10:00:00+00:00\"\n\
+             from:\n  id-type: crev\n  id: {}\n  url: \"https://example.com/{}\"\n\
+             ids:\n  - id-type: crev\n    id: {}\n    url: \"https://example.com/{}\"\n\
+             trust: {}\n\
+             ----- BEGIN CREV PROOF SIGNATURE -----\n\
+             signature\n\
+             ----- END CREV PROOF -----\n",
+            from, from, id, id, trust
+        )
+    }
+
+    /// a package review of `from` for a version of a crate
+    fn review_proof(from: &str, source: &str, name: &str, version: &str) -> String {
+        format!(
+            "----- BEGIN CREV PROOF -----\n\
+             kind: package review\n\
+             version: -1\n\
+             date: \"2021-03-01T10:00:00+00:00\"\n\
+             from:\n  id-type: crev\n  id: {}\n  url: \"https://example.com/{}\"\n\
+             package:\n  source: \"{}\"\n  name: {}\n  version: {}\n  digest: abc\n\
+             review:\n  thoroughness: low\n  understanding: medium\n  rating: positive\n\
+             comment: |-\n  Looks good.\n\n  Uses some unsafe.\n\
+             ----- BEGIN CREV PROOF SIGNATURE -----\n\
+             signature\n\
+             ----- END CREV PROOF -----\n",
+            from, from, source, name, version
+        )
+    }
+
+    #[test]
+    fn package_reviews() {
+        let dir = TempDir::new("dephell_crev").unwrap();
+        let proofs = [
+            review_proof("a", "https://crates.io", "foo", "1.0.0"),
+            review_proof("x", "https://crates.io", "foo", "1.0.0"),
+            review_proof("a", "https://crates.io", "foo", "2.0.0"),
+            // only reviews of crates.io crates are kept
+            review_proof("a", "https://example.com", "foo", "1.0.0"),
+            trust_proof("root", "a", "medium"),
+        ];
+        fs::create_dir_all(dir.path().join("proofs")).unwrap();
+        fs::write(dir.path().join("proofs/reviews.crev"), proofs.concat()).unwrap();
+        fs::write(dir.path().join("ignored.txt"), &proofs[1]).unwrap();
+
+        let crev_proofs = CrevProofs::open(dir.path(), Some("root")).unwrap();
+        let reviews = crev_proofs.reviews_for("foo", "1.0.0");
+        let mut reviewers: Vec<(&str, Option<&str>)> = reviews
+            .iter()
+            .map(|review| (review.from.as_str(), review.trust.as_deref()))
+            .collect();
+        reviewers.sort();
+        assert_eq!(reviewers, vec![("a", Some("medium")), ("x", None)]);
+        let review = &reviews[0];
+        assert_eq!(review.url.as_deref(), Some("https://example.com/a"));
+        assert_eq!(review.date, "2021-03-01T10:00:00+00:00");
+        assert_eq!(review.thoroughness, "low");
+        assert_eq!(review.understanding, "medium");
+        assert_eq!(review.rating, "positive");
+        assert_eq!(
+            review.comment.as_deref(),
+            Some("Looks good.\n\nUses some unsafe.")
+        );
+        assert_eq!(crev_proofs.reviews_for("foo", "2.0.0").len(), 1);
+        assert!(crev_proofs.reviews_for("bar", "1.0.0").is_empty());
+
+        // without a root id, no reviewer is trusted
+        let crev_proofs = CrevProofs::open(dir.path(), None).unwrap();
+        assert!(crev_proofs
+            .reviews_for("foo", "1.0.0")
+            .iter()
+            .all(|review| review.trust.is_none()));
+    }
+
+    #[test]
+    fn trust_follows_the_root_id() {
+        let proofs: String = [
+            ("root", "a", "high"),
+            ("a", "b", "medium"),
+            ("b", "c", "high"),
+            ("a", "d", "low"),
+            ("d", "e", "high"),
+            // the proofs of an id without trust are not followed
+            ("root", "n", "none"),
+            ("n", "z", "high"),
+            // neither are the ones of ids outside of the web of trust
+            ("x", "y", "high"),
+        ]
+        .iter()
+        .map(|(from, id, trust)| trust_proof(from, id, trust))
+        .collect();
+        let trust = |proofs: &str| {
+            let proofs = parse_proofs(proofs);
+            let mut trust: Vec<(String, &str)> = web_of_trust(&proofs, "root")
+                .into_iter()
+                .map(|(id, level)| (id.to_string(), TRUST_LEVELS[level]))
+                .collect();
+            trust.sort();
+            trust
+        };
+        let expected = |expected: &[(&str, &'static str)]| -> Vec<(String, &'static str)> {
+            expected
+                .iter()
+                .map(|(id, level)| (id.to_string(), *level))
+                .collect()
+        };
+
+        // an id gets the lowest trust of the chain leading to it
+        assert_eq!(
+            trust(&proofs),
+            expected(&[
+                ("a", "high"),
+                ("b", "medium"),
+                ("c", "medium"),
+                ("d", "low"),
+                ("e", "low"),
+                ("n", "none"),
+                ("root", "high"),
+            ])
+        );
+        // distrust wins, and the proofs of a distrusted id are not followed
+        let proofs = proofs + &trust_proof("e", "b", "distrust");
+        assert_eq!(
+            trust(&proofs),
+            expected(&[
+                ("a", "high"),
+                ("b", "distrust"),
+                ("d", "low"),
+                ("e", "low"),
+                ("n", "none"),
+                ("root", "high"),
+            ])
+        );
+    }
+}
@@ -0,0 +1,450 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use tempdir::TempDir;
+
+use crate::analysis::SCHEMA_VERSION;
+use crate::{analyze_repo, AnalysisOptions, AnalysisReport, Error};
+
+//
+// Structures
+// ==========
+//
+
+/// Change is a value before and after.
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Change<T> {
+    pub old: T,
+    pub new: T,
+}
+
+impl Change<u64> {
+    /// the difference between the new and the old value
+    pub fn delta(&self) -> i64 {
+        self.new as i64 - self.old as i64
+    }
+}
+
+/// PackageDiff describes how a dependency (all of its versions) changed between two reports.
+/// The metrics of the different versions of a dependency are added up.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PackageDiff {
+    /// name of the dependency
+    pub name: String,
+    /// the versions imported (none if the dependency was added or removed)
+    pub versions: Change<Vec<String>>,
+    /// total number of lines-of-code (in all languages, rust included)
+    pub total_loc: Change<u64>,
+    /// total number of lines of unsafe code
+    pub total_unsafe_loc: Change<u64>,
+    /// number of transitive dependencies that are only imported by this dependency
+    pub exclusive_deps_introduced: Change<u64>,
+    /// root crates that import this dependency
+    pub root_importers: Change<Vec<String>>,
+}
+
+/// ReportDiff is the difference between two reports.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct ReportDiff {
+    /// dependencies that are only in the new report
+    pub added: Vec<PackageDiff>,
+    /// dependencies that are only in the old report
+    pub removed: Vec<PackageDiff>,
+    /// dependencies whose versions or metrics changed
+    pub changed: Vec<PackageDiff>,
+}
+
+//
+// Diff
+// ====
+//
+
+/// the metrics of every version of a dependency, added up
+#[derive(Default)]
+struct Aggregate {
+    versions: Vec<String>,
+    total_loc: u64,
+    total_unsafe_loc: u64,
+    exclusive_deps_introduced: u64,
+    root_importers: BTreeSet<String>,
+}
+
+fn aggregate(report: &AnalysisReport) -> BTreeMap<&str, Aggregate> {
+    let mut aggregates: BTreeMap<&str, Aggregate> = BTreeMap::new();
+    for package_risk in report.analysis_result.values() {
+        let aggregate = aggregates.entry(&package_risk.name).or_default();
+        aggregate.versions.push(package_risk.version.clone());
+        aggregate.total_loc += package_risk.total_loc;
+        aggregate.total_unsafe_loc += package_risk.total_unsafe_loc;
+        aggregate.exclusive_deps_introduced += package_risk.exclusive_deps_introduced.len() as u64;
+        aggregate
+            .root_importers
+            .extend(package_risk.root_importers.iter().cloned());
+    }
+    aggregates
+}
+
+/// Compares an `old` report with a `new` one. Dependencies are matched by name.
+pub fn diff_reports(old: &AnalysisReport, new: &AnalysisReport) -> ReportDiff {
+    let old = aggregate(old);
+    let new = aggregate(new);
+    let names: BTreeSet<&str> = old.keys().chain(new.keys()).cloned().collect();
+
+    let empty = Aggregate::default();
+    let mut report_diff = ReportDiff::default();
+    for name in names {
+        let (old_aggregate, new_aggregate) = (old.get(name), new.get(name));
+        let (o, n) = (
+            old_aggregate.unwrap_or(&empty),
+            new_aggregate.unwrap_or(&empty),
+        );
+        let package_diff = PackageDiff {
+            name: name.to_string(),
+            versions: Change {
+                old: o.versions.clone(),
+                new: n.versions.clone(),
+            },
+            total_loc: Change {
+                old: o.total_loc,
+                new: n.total_loc,
+            },
+            total_unsafe_loc: Change {
+                old: o.total_unsafe_loc,
+                new: n.total_unsafe_loc,
+            },
+            exclusive_deps_introduced: Change {
+                old: o.exclusive_deps_introduced,
+                new: n.exclusive_deps_introduced,
+            },
+            root_importers: Change {
+                old: o.root_importers.iter().cloned().collect(),
+                new: n.root_importers.iter().cloned().collect(),
+            },
+        };
+        match (old_aggregate, new_aggregate) {
+            (None, _) => report_diff.added.push(package_diff),
+            (_, None) => report_diff.removed.push(package_diff),
+            _ => {
+                if package_diff.versions.old != package_diff.versions.new
+                    || package_diff.total_loc.delta() != 0
+                    || package_diff.total_unsafe_loc.delta() != 0
+                    || package_diff.exclusive_deps_introduced.delta() != 0
+                    || package_diff.root_importers.old != package_diff.root_importers.new
+                {
+                    report_diff.changed.push(package_diff);
+                }
+            }
+        }
+    }
+    report_diff
+}
+
+/// Reads a JSON report (as printed by cargo dephell).
+/// Reports of another schema version are rejected, as some of their fields have a different
+/// meaning: they need to be generated again with this version of cargo dephell.
+pub fn read_report(path: &Path) -> Result<AnalysisReport, Error> {
+    let content = fs::read(path)?;
+    let report: serde_json::Value = serde_json::from_slice(&content)
+        .map_err(|err| Error::InvalidReport(path.to_path_buf(), err.to_string()))?;
+    let schema_version = report.get("schema_version").and_then(|v| v.as_u64());
+    let tool_version = report
+        .get("tool_version")
+        .and_then(|v| v.as_str())
+        .unwrap_or("unknown");
+    let reason = match schema_version {
+        Some(version) if version == SCHEMA_VERSION as u64 => None,
+        Some(version) if version < SCHEMA_VERSION as u64 => Some(format!(
+            "it was generated by an older cargo-dephell ({}) with schema version {}, \
+             generate it again with this version (schema version {})",
+            tool_version, version, SCHEMA_VERSION
+        )),
+        Some(version) => Some(format!(
+            "it was generated by a newer cargo-dephell ({}) with schema version {}, \
+             upgrade cargo-dephell to read it (this version reads schema version {})",
+            tool_version, version, SCHEMA_VERSION
+        )),
+        None => Some("no schema version, it is not a cargo-dephell report".to_string()),
+    };
+    if let Some(reason) = reason {
+        return Err(Error::InvalidReport(path.to_path_buf(), reason));
+    }
+    serde_json::from_value(report)
+        .map_err(|err| Error::InvalidReport(path.to_path_buf(), err.to_string()))
+}
+
+/// Analyzes the same crate or workspace as `options`, but as it is at `git_ref`
+/// (the commit is checked out in a temporary git worktree).
+pub fn analyze_git_ref(options: &AnalysisOptions, git_ref: &str) -> Result<AnalysisReport, Error> {
+    // find the manifest path relative to the repository
+    let manifest_path = options.manifest_path().canonicalize()?;
+    let manifest_dir = manifest_path.parent().unwrap_or_else(|| Path::new("/"));
+    let toplevel = run_git(git(manifest_dir).args(["rev-parse", "--show-toplevel"]))?;
+    let toplevel = Path::new(&toplevel).canonicalize()?;
+    let relative_manifest_path = manifest_path
+        .strip_prefix(&toplevel)
+        .map_err(|_| Error::Git("the manifest path is not in the git repository".to_string()))?;
+
+    // check out the git reference
+    let tmp_dir = TempDir::new("dephell_git_ref")?;
+    let worktree = tmp_dir.path().join("worktree");
+    run_git(
+        git(&toplevel)
+            .args(["worktree", "add", "--detach"])
+            .arg(&worktree)
+            .arg(git_ref),
+    )?;
+
+    // analyze it
+    let options = options
+        .clone()
+        .with_manifest_path(worktree.join(relative_manifest_path));
+    let report = analyze_repo(&options);
+
+    // always remove the worktree (a failed analysis is reported before a failed removal)
+    let removed = run_git(
+        git(&toplevel)
+            .args(["worktree", "remove", "--force"])
+            .arg(&worktree),
+    );
+    let report = report?;
+    removed?;
+    Ok(report)
+}
+
+/// a git command run in `dir`
+pub(crate) fn git(dir: &Path) -> Command {
+    let mut cmd = Command::new("git");
+    cmd.arg("-C").arg(dir);
+    cmd
+}
+
+/// runs a git command and returns its output
+pub(crate) fn run_git(cmd: &mut Command) -> Result<String, Error> {
+    let output = cmd.output()?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        return Err(Error::Git(stderr));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+//
+// Output
+// ======
+//
+
+/// formats a delta (`+3`, `-2`, or `0`)
+fn delta(change: &Change<u64>) -> String {
+    match change.delta() {
+        0 => "0".to_string(),
+        d if d > 0 => format!("+{}", d),
+        d => d.to_string(),
+    }
+}
+
+/// formats a change of value (`4` if it didn't change, `3 → 4 (+1)` otherwise).
+/// Only the new (or old) value is shown for added (or removed) dependencies.
+fn value_change(package: &PackageDiff, change: &Change<u64>) -> String {
+    if package.versions.old.is_empty() || change.old == change.new {
+        change.new.to_string()
+    } else if package.versions.new.is_empty() {
+        change.old.to_string()
+    } else {
+        format!("{} → {} ({})", change.old, change.new, delta(change))
+    }
+}
+
+/// formats a change of list (`a, b` if it didn't change, `a, b → b, c` otherwise)
+fn list_change(change: &Change<Vec<String>>) -> String {
+    let (old, new) = (change.old.join(", "), change.new.join(", "));
+    if change.old.is_empty() || old == new {
+        new
+    } else if change.new.is_empty() {
+        old
+    } else {
+        format!("{} → {}", old, new)
+    }
+}
+
+impl ReportDiff {
+    /// is there any change?
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.changed.is_empty()
+    }
+
+    /// a one line summary of the changes
+    fn summary(&self) -> String {
+        format!(
+            "{} added, {} removed, {} changed dependencies",
+            self.added.len(),
+            self.removed.len(),
+            self.changed.len()
+        )
+    }
+
+    /// renders the diff for a terminal
+    pub fn to_terminal(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "dephell: {}", self.summary()).unwrap();
+        let sections = [
+            ("added", "+", &self.added),
+            ("removed", "-", &self.removed),
+            ("changed", "~", &self.changed),
+        ];
+        for (title, sign, packages) in sections.iter() {
+            if packages.is_empty() {
+                continue;
+            }
+            writeln!(out, "\n{}:", title).unwrap();
+            for package in packages.iter() {
+                writeln!(
+                    out,
+                    "  {} {} {}: total loc {}, total unsafe loc {}, \
+                     exclusive deps {}, root importers [{}]",
+                    sign,
+                    package.name,
+                    list_change(&package.versions),
+                    value_change(package, &package.total_loc),
+                    value_change(package, &package.total_unsafe_loc),
+                    value_change(package, &package.exclusive_deps_introduced),
+                    list_change(&package.root_importers),
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+
+    /// renders the diff as Markdown (for example to comment on a pull request)
+    pub fn to_markdown(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "### Dependency changes\n").unwrap();
+        writeln!(out, "{}.", self.summary()).unwrap();
+        if self.is_empty() {
+            return out;
+        }
+        writeln!(out).unwrap();
+        writeln!(
+            out,
+            "| | crate | versions | total LOC | total unsafe LOC \
+             | exclusive deps | root importers |"
+        )
+        .unwrap();
+        writeln!(out, "|---|---|---|---|---|---|---|").unwrap();
+        let sections = [
+            ("added", &self.added),
+            ("removed", &self.removed),
+            ("changed", &self.changed),
+        ];
+        for (title, packages) in sections.iter() {
+            for package in packages.iter() {
+                writeln!(
+                    out,
+                    "| {} | `{}` | {} | {} | {} | {} | {} |",
+                    title,
+                    package.name,
+                    list_change(&package.versions),
+                    value_change(package, &package.total_loc),
+                    value_change(package, &package.total_unsafe_loc),
+                    value_change(package, &package.exclusive_deps_introduced),
+                    list_change(&package.root_importers),
+                )
+                .unwrap();
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PackageRisk;
+    use chrono::Utc;
+
+    /// a report with packages given as (name, version, total loc, root importers)
+    fn report(packages: &[(&str, &str, u64, &[&str])]) -> AnalysisReport {
+        let analysis_result = packages
+            .iter()
+            .map(|(name, version, total_loc, root_importers)| {
+                let package_risk = PackageRisk {
+                    name: name.to_string(),
+                    version: version.to_string(),
+                    total_loc: *total_loc,
+                    root_importers: root_importers.iter().map(|r| r.to_string()).collect(),
+                    ..PackageRisk::default()
+                };
+                (format!("{} {}", name, version), package_risk)
+            })
+            .collect();
+        AnalysisReport {
+            schema_version: SCHEMA_VERSION,
+            tool_version: "test".to_string(),
+            generated_at: Utc::now(),
+            manifest_path: "Cargo.toml".into(),
+            options: AnalysisOptions::new("Cargo.toml"),
+            root_crates: BTreeSet::new(),
+            main_dependencies: BTreeSet::new(),
+            dependency_edges: Vec::new(),
+            analysis_result,
+            duplicate_versions: BTreeMap::new(),
+            unavailable_metrics: BTreeMap::new(),
+        }
+    }
+
+    fn names(packages: &[PackageDiff]) -> Vec<&str> {
+        packages
+            .iter()
+            .map(|package| package.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn dependencies_are_matched_by_name() {
+        let old = report(&[
+            ("same", "1.0.0", 10, &["app"]),
+            ("upgraded", "1.0.0", 10, &["app"]),
+            ("grown", "1.0.0", 10, &["app"]),
+            ("removed", "1.0.0", 10, &["app"]),
+            ("duplicated", "1.0.0", 10, &["app"]),
+        ]);
+        let new = report(&[
+            ("same", "1.0.0", 10, &["app"]),
+            ("upgraded", "2.0.0", 10, &["app"]),
+            ("grown", "1.0.0", 15, &["app", "cli"]),
+            ("added", "1.0.0", 10, &["cli"]),
+            ("duplicated", "1.0.0", 10, &["app"]),
+            ("duplicated", "2.0.0", 5, &["cli"]),
+        ]);
+        let diff = diff_reports(&old, &new);
+        assert_eq!(names(&diff.added), vec!["added"]);
+        assert_eq!(names(&diff.removed), vec!["removed"]);
+        assert_eq!(
+            names(&diff.changed),
+            vec!["duplicated", "grown", "upgraded"]
+        );
+
+        // the versions of a dependency are added up
+        let duplicated = &diff.changed[0];
+        assert_eq!(
+            duplicated.versions,
+            Change {
+                old: vec!["1.0.0".to_string()],
+                new: vec!["1.0.0".to_string(), "2.0.0".to_string()],
+            }
+        );
+        assert_eq!(duplicated.total_loc.delta(), 5);
+        assert_eq!(
+            duplicated.root_importers.new,
+            vec!["app".to_string(), "cli".to_string()]
+        );
+
+        // added and removed dependencies have no old (or new) metrics
+        assert_eq!(diff.added[0].total_loc, Change { old: 0, new: 10 });
+        assert_eq!(diff.removed[0].total_loc, Change { old: 10, new: 0 });
+        assert!(diff_reports(&old, &old).is_empty());
+    }
+}
@@ -0,0 +1,154 @@
+use std::fmt;
+
+/// Error is returned when an analysis can't be completed.
+#[derive(Debug)]
+pub enum Error {
+    /// the package graph could not be obtained or queried (via guppy)
+    Graph(guppy::Error),
+    /// the given target triple is not known
+    UnknownTarget(String, guppy::TargetSpecError),
+    /// the selection of workspace crates is empty
+    NoPackageToAnalyze,
+    /// a `dependency/feature` was given for a dependency that none of the root crates has
+    UnknownDependency(String),
+    /// `cargo check` failed on the manifest path (contains cargo's stderr)
+    Build(String),
+    /// the cfg options of a target could not be obtained from rustc (contains rustc's stderr)
+    Rustc(String),
+    /// the HTTP client could not be created
+    Http(reqwest::Error),
+    /// an I/O error happened (for example when spawning cargo)
+    Io(std::io::Error),
+    /// a configuration file could not be parsed (contains the path of the file and the reason)
+    Config(std::path::PathBuf, String),
+    /// a report could not be read (contains the path of the report and the reason)
+    InvalidReport(std::path::PathBuf, String),
+    /// a git command failed (contains git's stderr)
+    Git(String),
+    /// the pool of threads used to analyze packages could not be created
+    ThreadPool(rayon::ThreadPoolBuildError),
+    /// a risk weight was given for a metric that is not part of the risk score
+    UnknownRiskMetric(String),
+    /// the advisory database could not be read (contains the path of the faulty file and the reason)
+    AdvisoryDb(std::path::PathBuf, String),
+    /// an audit ledger could not be parsed (contains the path of the file and the reason)
+    AuditFile(std::path::PathBuf, String),
+    /// a cargo-vet file could not be parsed (contains the path of the file and the reason)
+    Vet(std::path::PathBuf, String),
+    /// crev proofs could not be read (contains the path of the proofs and the reason)
+    Crev(std::path::PathBuf, String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Graph(err) => write!(f, "dephell: {}", err),
+            Error::UnknownTarget(target, err) => {
+                write!(f, "dephell: unsupported target {}: {}", target, err)
+            }
+            Error::NoPackageToAnalyze => write!(f, "dephell: no package to analyze was found"),
+            Error::UnknownDependency(feature) => write!(
+                f,
+                "dephell: none of the root crates has a dependency named after the feature {}",
+                feature
+            ),
+            Error::Build(stderr) => write!(
+                f,
+                "dephell: could not build the target manifest path (use --no-build to analyze it without building).\n{}",
+                stderr
+            ),
+            Error::Rustc(stderr) => write!(f, "dephell: rustc failed: {}", stderr),
+            Error::Http(err) => write!(f, "dephell: {}", err),
+            Error::Io(err) => write!(f, "dephell: {}", err),
+            Error::Config(path, err) => {
+                write!(
+                    f,
+                    "dephell: invalid configuration in {}: {}",
+                    path.display(),
+                    err
+                )
+            }
+            Error::InvalidReport(path, err) => {
+                write!(f, "dephell: invalid report {}: {}", path.display(), err)
+            }
+            Error::Git(stderr) => write!(f, "dephell: git failed: {}", stderr),
+            Error::ThreadPool(err) => write!(f, "dephell: {}", err),
+            Error::UnknownRiskMetric(metric) => {
+                write!(f, "dephell: {} is not a metric of the risk score", metric)
+            }
+            Error::AdvisoryDb(path, err) => write!(
+                f,
+                "dephell: invalid advisory database at {}: {}",
+                path.display(),
+                err
+            ),
+            Error::AuditFile(path, err) => {
+                write!(f, "dephell: invalid audit file {}: {}", path.display(), err)
+            }
+            Error::Vet(path, err) => {
+                write!(
+                    f,
+                    "dephell: invalid cargo-vet file {}: {}",
+                    path.display(),
+                    err
+                )
+            }
+            Error::Crev(path, err) => {
+                write!(
+                    f,
+                    "dephell: invalid crev proofs {}: {}",
+                    path.display(),
+                    err
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Graph(err) => Some(err),
+            Error::UnknownTarget(_, err) => Some(err),
+            Error::Http(err) => Some(err),
+            Error::Io(err) => Some(err),
+            Error::ThreadPool(err) => Some(err),
+            Error::NoPackageToAnalyze
+            | Error::UnknownDependency(_)
+            | Error::Build(_)
+            | Error::Rustc(_)
+            | Error::Config(..)
+            | Error::InvalidReport(..)
+            | Error::Git(_)
+            | Error::UnknownRiskMetric(_)
+            | Error::AdvisoryDb(..)
+            | Error::AuditFile(..)
+            | Error::Vet(..)
+            | Error::Crev(..) => None,
+        }
+    }
+}
+
+impl From<guppy::Error> for Error {
+    fn from(err: guppy::Error) -> Self {
+        Error::Graph(err)
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(err: reqwest::Error) -> Self {
+        Error::Http(err)
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<rayon::ThreadPoolBuildError> for Error {
+    fn from(err: rayon::ThreadPoolBuildError) -> Self {
+        Error::ThreadPool(err)
+    }
+}
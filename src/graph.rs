@@ -0,0 +1,133 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+use crate::{AnalysisReport, DependencyKind, PackageRisk};
+
+/// ColorBy is the metric used to color the packages of the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorBy {
+    /// the risk score (see `PackageRisk::risk`)
+    Risk,
+    /// the number of lines of unsafe code (relative to the package with the most)
+    UnsafeLoc,
+}
+
+/// the color of the riskiest packages (the others fade to white)
+const RISKY_COLOR: (f64, f64, f64) = (217.0, 83.0, 79.0);
+
+/// the color of the root crates
+const ROOT_COLOR: &str = "#9ecae1";
+
+/// Returns the dependency graph of a report in the DOT format of graphviz
+/// (https://graphviz.org), with one node per package (identified by its key,
+/// see `package_key`) and one edge per dependency, labelled with its kind.
+pub fn to_dot(report: &AnalysisReport, color_by: ColorBy) -> String {
+    let max_unsafe_loc = report
+        .analysis_result
+        .values()
+        .map(|package_risk| package_risk.unsafe_loc)
+        .max()
+        .unwrap_or(0);
+    let color = |package_risk: &PackageRisk| -> f64 {
+        match color_by {
+            ColorBy::Risk => package_risk.risk.score / 100.0,
+            ColorBy::UnsafeLoc if max_unsafe_loc == 0 => 0.0,
+            ColorBy::UnsafeLoc => {
+                (package_risk.unsafe_loc as f64).ln_1p() / (max_unsafe_loc as f64).ln_1p()
+            }
+        }
+    };
+
+    let mut dot = String::new();
+    writeln!(dot, "digraph dependencies {{").unwrap();
+    writeln!(dot, "    rankdir=LR;").unwrap();
+    writeln!(
+        dot,
+        "    node [shape=box, style=\"rounded,filled\", fontname=\"sans-serif\"];"
+    )
+    .unwrap();
+    writeln!(dot, "    edge [fontname=\"sans-serif\", fontsize=10];").unwrap();
+
+    // root crates are not part of the analysis result
+    let roots: BTreeSet<&str> = report
+        .dependency_edges
+        .iter()
+        .map(|edge| edge.from.as_str())
+        .filter(|from| !report.analysis_result.contains_key(*from))
+        .collect();
+    for root in roots {
+        writeln!(
+            dot,
+            "    {} [fillcolor=\"{}\", style=\"rounded,filled,bold\"];",
+            quote(root),
+            ROOT_COLOR
+        )
+        .unwrap();
+    }
+
+    for (key, package_risk) in &report.analysis_result {
+        let metric = match color_by {
+            ColorBy::Risk => format!("risk {:.1}", package_risk.risk.score),
+            ColorBy::UnsafeLoc => format!("{} unsafe loc", package_risk.unsafe_loc),
+        };
+        let label = format!(
+            "{}\n{}\n{}",
+            package_risk.name, package_risk.version, metric
+        );
+        let fillcolor = if package_risk.internal {
+            ROOT_COLOR.to_string()
+        } else {
+            shade(color(package_risk))
+        };
+        let mut style = "rounded,filled".to_string();
+        if package_risk.used == Some(false) {
+            style.push_str(",dashed");
+        }
+        writeln!(
+            dot,
+            "    {} [label={}, fillcolor=\"{}\", style=\"{}\"];",
+            quote(key),
+            quote(&label),
+            fillcolor,
+            style
+        )
+        .unwrap();
+    }
+
+    for edge in &report.dependency_edges {
+        let (label, style) = match edge.kind {
+            DependencyKind::Normal => ("normal", "solid"),
+            DependencyKind::Build => ("build", "dotted"),
+            DependencyKind::Dev => ("dev", "dashed"),
+        };
+        writeln!(
+            dot,
+            "    {} -> {} [label=\"{}\", style={}];",
+            quote(&edge.from),
+            quote(&edge.to),
+            label,
+            style
+        )
+        .unwrap();
+    }
+
+    writeln!(dot, "}}").unwrap();
+    dot
+}
+
+/// a quoted DOT identifier
+fn quote(id: &str) -> String {
+    let escaped = id
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n");
+    format!("\"{}\"", escaped)
+}
+
+/// a color between white (0) and the risky color (1)
+fn shade(value: f64) -> String {
+    let value = value.clamp(0.0, 1.0);
+    let mix = |risky: f64| (255.0 - (255.0 - risky) * value).round() as u8;
+    let (red, green, blue) = RISKY_COLOR;
+    format!("#{:02x}{:02x}{:02x}", mix(red), mix(green), mix(blue))
+}
@@ -0,0 +1,40 @@
+//! **Cargo dephell** analyzes the third-party dependencies imported by a rust crate
+//! or rust workspace.
+//!
+//! The analysis can be embedded via [`analyze_repo`](analyze_repo),
+//! configured with [`AnalysisOptions`](AnalysisOptions):
+//!
+//! ```no_run
+//! use cargo_dephell::{analyze_repo, AnalysisOptions};
+//!
+//! let report = analyze_repo(&AnalysisOptions::new("./Cargo.toml")).unwrap();
+//! for (package, package_risk) in &report.analysis_result {
+//!     println!("{}: {} lines of rust", package, package_risk.total_rust_loc);
+//! }
+//! ```
+
+pub mod advisory;
+pub mod analysis;
+pub mod audit;
+mod cache;
+pub mod config;
+pub mod crev;
+pub mod diff;
+mod error;
+pub mod graph;
+mod metrics;
+mod modules;
+pub mod policy;
+pub mod resolve;
+pub mod risk;
+pub mod source;
+pub mod unsafety;
+pub mod vet;
+
+pub use analysis::{
+    analyze_repo, report_schema, AnalysisOptions, AnalysisReport, DependencyEdge, DuplicateVersion,
+    FileMetrics, PackageRisk, SCHEMA_VERSION,
+};
+pub use config::Config;
+pub use error::Error;
+pub use resolve::{DependencyKind, FeatureSelection};
@@ -1,12 +1,14 @@
-use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
-
 use askama::Template;
-use clap::{App, Arg};
-use serde::{Deserialize, Serialize};
-
-mod analysis;
-mod metrics;
+use cargo_dephell::audit::{Audit, AuditLedger, AuditScope, AUDIT_FILE_NAME};
+use cargo_dephell::diff::{analyze_git_ref, diff_reports, read_report};
+use cargo_dephell::graph::{to_dot, ColorBy};
+use cargo_dephell::policy::check_policy;
+use cargo_dephell::{analyze_repo, report_schema, AnalysisOptions, Config, FeatureSelection};
+use chrono::Local;
+use clap::{App, Arg, ArgMatches, SubCommand};
+use std::convert::TryFrom;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 //
 // HTML Stuff
@@ -19,18 +21,404 @@ struct HtmlList {
     name: String,
     // TODO: we might want to compress/base64 this to lighten the HTML output
     json_result: String,
+    /// the dependency graph, in the DOT format (base64)
+    dot_graph: String,
 }
 
 //
-// JSON Stuff
-// ==========
+// Arguments
+// =========
 //
 
-#[derive(Serialize, Deserialize)]
-struct JsonResult {
-    root_crates: HashSet<String>,
-    main_dependencies: HashSet<String>,
-    analysis_result: HashMap<String, analysis::PackageRisk>,
+/// the arguments that locate the crate or workspace and the files describing it
+fn input_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("manifest-path")
+            .help("Sets the path to the Cargo.toml to analyze")
+            .short("m")
+            .long("manifest-path")
+            .takes_value(true)
+            .value_name("PATH"),
+        Arg::with_name("config")
+            .long("config")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("sets the configuration file (default dephell.toml or the Cargo.toml metadata)"),
+        Arg::with_name("audit-file")
+            .long("audit-file")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("sets the audit ledger (default AUDIT.toml next to the Cargo.toml)"),
+    ]
+}
+
+/// the arguments that configure an analysis (shared by every mode)
+fn analysis_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let mut args = input_args();
+    args.extend(vec![
+        Arg::with_name("package")
+            .short("p")
+            .multiple(true)
+            .takes_value(true)
+            .value_name("PACKAGE")
+            .help("can be used to specify exactly which packages in a workspace to use"),
+        Arg::with_name("github-token")
+            .long("github-token")
+            .takes_value(true)
+            .value_name("USER:TOKEN")
+            .help("allows the CLI to retrieve github repos stats"),
+        Arg::with_name("proxy")
+            .long("proxy")
+            .takes_value(true)
+            .value_name("PROTOCOL://IP:PORT")
+            .help("uses a proxy to make external requests to github"),
+        Arg::with_name("ignore-workspace")
+            .short("i")
+            .multiple(true)
+            .takes_value(true)
+            .value_name("CRATE_NAME")
+            .conflicts_with("package")
+            .help("can be used multiple times to list workplace crates to ignore"),
+        Arg::with_name("features")
+            .long("features")
+            .multiple(true)
+            .takes_value(true)
+            .value_name("FEATURES")
+            .help("space or comma separated list of features to activate"),
+        Arg::with_name("all-features")
+            .long("all-features")
+            .help("activate all available features"),
+        Arg::with_name("no-default-features")
+            .long("no-default-features")
+            .help("do not activate the `default` feature"),
+        Arg::with_name("target")
+            .long("target")
+            .multiple(true)
+            .takes_value(true)
+            .number_of_values(1)
+            .value_name("TRIPLE")
+            .help("can be used multiple times to analyze the build for specific target triples"),
+        Arg::with_name("include-dev")
+            .long("include-dev")
+            .help("also analyze the dev-dependencies of the workspace crates"),
+        Arg::with_name("offline")
+            .long("offline")
+            .help("do not access the network (network metrics are not collected)"),
+        Arg::with_name("jobs")
+            .short("j")
+            .long("jobs")
+            .takes_value(true)
+            .value_name("N")
+            .help("number of packages to analyze in parallel (default number of CPUs)"),
+        Arg::with_name("cache-dir")
+            .long("cache-dir")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("where to cache network responses (default ~/.cache/cargo-dephell)"),
+        Arg::with_name("cache-ttl")
+            .long("cache-ttl")
+            .takes_value(true)
+            .value_name("HOURS")
+            .help("how long cached responses are used (default 24 hours)"),
+        Arg::with_name("refresh")
+            .long("refresh")
+            .help("ignore the cache and query crates.io and github again"),
+        Arg::with_name("target-dir")
+            .long("target-dir")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("where to check the dependencies, kept between runs (default a temporary one)"),
+        Arg::with_name("reuse-target-dir")
+            .long("reuse-target-dir")
+            .help("check the dependencies in the workspace's target directory, kept between runs"),
+        Arg::with_name("no-build")
+            .long("no-build")
+            .conflicts_with_all(&["target-dir", "reuse-target-dir"])
+            .help("do not check the dependencies (build metrics are not collected)"),
+        Arg::with_name("advisory-db")
+            .long("advisory-db")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("a local checkout of the RustSec advisory-db, to report advisories"),
+        Arg::with_name("vet-dir")
+            .long("vet-dir")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("sets the cargo-vet store (default supply-chain next to the Cargo.toml)"),
+        Arg::with_name("crev-proofs")
+            .long("crev-proofs")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("a folder containing crev proofs, to report crev reviews"),
+        Arg::with_name("crev-id")
+            .long("crev-id")
+            .takes_value(true)
+            .value_name("ID")
+            .help("the crev id whose web of trust gives the trust level of reviewers"),
+        Arg::with_name("verify-sources")
+            .long("verify-sources")
+            .help("compare the published crates with their repositories (cloned in the cache)"),
+        Arg::with_name("source-mirror")
+            .long("source-mirror")
+            .takes_value(true)
+            .value_name("PATH")
+            .help("a folder containing clones of the repositories, to verify the sources"),
+        Arg::with_name("risk-weight")
+            .long("risk-weight")
+            .multiple(true)
+            .number_of_values(1)
+            .takes_value(true)
+            .value_name("METRIC=WEIGHT")
+            .help("overrides the weight of a metric in the risk score (0 removes it)"),
+        Arg::with_name("quiet")
+            .short("q")
+            .help("suppress any output to stdout"),
+    ]);
+    args
+}
+
+/// the arguments that override the thresholds of the policy
+fn policy_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("max-total-unsafe-loc")
+            .long("max-total-unsafe-loc")
+            .takes_value(true)
+            .value_name("N")
+            .help("maximum lines of unsafe code of a dependency (including its dependencies)"),
+        Arg::with_name("max-transitive-deps")
+            .long("max-transitive-deps")
+            .takes_value(true)
+            .value_name("N")
+            .help("maximum number of transitive dependencies pulled by a direct dependency"),
+        Arg::with_name("min-crates-io-dependent")
+            .long("min-crates-io-dependent")
+            .takes_value(true)
+            .value_name("N")
+            .help("minimum number of crates.io crates depending on a dependency"),
+        Arg::with_name("max-days-since-update")
+            .long("max-days-since-update")
+            .takes_value(true)
+            .value_name("DAYS")
+            .help("maximum number of days since a dependency was last updated on crates.io"),
+        Arg::with_name("forbid-duplicate-versions")
+            .long("forbid-duplicate-versions")
+            .help("forbid dependencies imported with several versions"),
+        Arg::with_name("forbid-advisories")
+            .long("forbid-advisories")
+            .help("forbid dependencies affected by a vulnerability or unsoundness advisory"),
+        Arg::with_name("forbid-unmaintained")
+            .long("forbid-unmaintained")
+            .help("forbid dependencies flagged as unmaintained by an advisory"),
+        Arg::with_name("forbid-yanked")
+            .long("forbid-yanked")
+            .help("forbid dependencies yanked from crates.io"),
+    ]
+}
+
+//
+// Configuration
+// =============
+//
+
+/// obtains the manifest path to analyze and its configuration
+fn read_config(matches: &ArgMatches) -> Result<(String, Config), cargo_dephell::Error> {
+    // get metadata from manifest path
+    let manifest_path = matches
+        .value_of("manifest-path")
+        .map(|s| s.to_owned())
+        .unwrap_or_else(|| {
+            let mut current_dir = std::env::current_dir().unwrap();
+            current_dir.push("Cargo.toml");
+            current_dir.to_str().unwrap().to_owned()
+        });
+
+    // read the configuration (command-line flags take precedence over it)
+    let config = match matches.value_of("config") {
+        Some(config_path) => Config::from_file(Path::new(config_path))?,
+        None => Config::discover(Path::new(&manifest_path))?
+            .map(|(_, config)| config)
+            .unwrap_or_default(),
+    };
+    Ok((manifest_path, config))
+}
+
+/// the audit ledger given on the command-line or in the configuration (if any)
+fn audit_file(matches: &ArgMatches, config: &Config) -> Option<PathBuf> {
+    matches
+        .value_of("audit-file")
+        .map(PathBuf::from)
+        .or_else(|| config.audit_file.clone())
+}
+
+/// configures the analysis from the command-line flags and the configuration
+fn analysis_options(
+    matches: &ArgMatches,
+    manifest_path: &str,
+    config: &Config,
+    quiet: bool,
+) -> Result<AnalysisOptions, String> {
+    let mut options = AnalysisOptions::new(manifest_path).quiet(quiet);
+
+    // parse github token (if given)
+    if let Some(github_token) = matches.value_of("github-token") {
+        let github_token: Vec<&str> = github_token.split(':').collect();
+        if github_token.len() != 2 {
+            eprintln!("wrong github-token, must be of the form username:token");
+        } else {
+            options = options.github_token(github_token[0], github_token[1]);
+        }
+    }
+
+    // use a proxy to make external requests (if given)
+    if let Some(proxy) = matches.value_of("proxy").or(config.proxy.as_deref()) {
+        options = options.proxy(proxy);
+    }
+
+    // parse dependencies to ignore
+    if let Some(to_ignore) = matches.values_of("ignore-workspace") {
+        options = options.ignore(to_ignore);
+    } else if let Some(to_ignore) = &config.ignore {
+        options = options.ignore(to_ignore);
+    }
+
+    // parse packages to use
+    if let Some(packages) = matches.values_of("package") {
+        options = options.packages(packages);
+    } else if let Some(packages) = &config.packages {
+        options = options.packages(packages);
+    }
+
+    // parse features to use
+    let features = FeatureSelection {
+        features: matches
+            .values_of("features")
+            .map(FeatureSelection::parse_features)
+            .or_else(|| config.features.clone())
+            .unwrap_or_default(),
+        all_features: matches.is_present("all-features") || config.all_features.unwrap_or(false),
+        no_default_features: matches.is_present("no-default-features")
+            || config.no_default_features.unwrap_or(false),
+    };
+    options = options.features(features);
+
+    // parse targets to build for
+    if let Some(targets) = matches.values_of("target") {
+        options = options.targets(targets);
+    } else if let Some(targets) = &config.targets {
+        options = options.targets(targets);
+    }
+
+    // include dev dependencies
+    options = options
+        .include_dev(matches.is_present("include-dev") || config.include_dev.unwrap_or(false));
+
+    // do not access the network
+    options = options.offline(matches.is_present("offline") || config.offline.unwrap_or(false));
+
+    // configure the cache of network responses
+    if let Some(cache_dir) = matches.value_of("cache-dir") {
+        options = options.cache_dir(Some(cache_dir.into()));
+    } else if let Some(cache_dir) = &config.cache_dir {
+        options = options.cache_dir(Some(cache_dir.clone()));
+    }
+    let cache_ttl = match matches.value_of("cache-ttl").map(str::parse::<u64>) {
+        Some(Ok(hours)) => Some(hours),
+        Some(Err(_)) => {
+            return Err("wrong cache-ttl, must be a number of hours".to_string());
+        }
+        None => config.cache_ttl,
+    };
+    if let Some(hours) = cache_ttl {
+        let secs = hours
+            .checked_mul(60 * 60)
+            .ok_or_else(|| "wrong cache-ttl, must be a number of hours".to_string())?;
+        options = options.cache_ttl(Duration::from_secs(secs));
+    }
+    options = options.refresh(matches.is_present("refresh"));
+
+    // number of packages to analyze in parallel
+    let jobs = match matches.value_of("jobs").map(str::parse::<usize>) {
+        Some(Ok(jobs)) => Some(jobs),
+        Some(Err(_)) => Some(0),
+        None => config.jobs,
+    };
+    match jobs {
+        Some(0) => return Err("wrong jobs, must be a positive number".to_string()),
+        Some(jobs) => options = options.jobs(jobs),
+        None => (),
+    }
+
+    // where the dependencies are checked
+    if let Some(target_dir) = matches.value_of("target-dir") {
+        options = options.target_dir(target_dir);
+    } else if let Some(target_dir) = &config.target_dir {
+        options = options.target_dir(target_dir);
+    }
+    options = options.reuse_target_dir(
+        matches.is_present("reuse-target-dir") || config.reuse_target_dir.unwrap_or(false),
+    );
+    options = options.no_build(matches.is_present("no-build") || config.no_build.unwrap_or(false));
+
+    // the RustSec advisory database
+    if let Some(advisory_db) = matches.value_of("advisory-db") {
+        options = options.advisory_db(advisory_db);
+    } else if let Some(advisory_db) = &config.advisory_db {
+        options = options.advisory_db(advisory_db);
+    }
+
+    // cargo-vet and crev
+    if let Some(vet_dir) = matches.value_of("vet-dir") {
+        options = options.vet_dir(vet_dir);
+    } else if let Some(vet_dir) = &config.vet_dir {
+        options = options.vet_dir(vet_dir);
+    }
+    if let Some(crev_proofs) = matches.value_of("crev-proofs") {
+        options = options.crev_proofs(crev_proofs);
+    } else if let Some(crev_proofs) = &config.crev_proofs {
+        options = options.crev_proofs(crev_proofs);
+    }
+    if let Some(crev_id) = matches.value_of("crev-id") {
+        options = options.crev_id(crev_id);
+    } else if let Some(crev_id) = &config.crev_id {
+        options = options.crev_id(crev_id);
+    }
+
+    // compare the published crates with their repositories
+    options = options.verify_sources(
+        matches.is_present("verify-sources") || config.verify_sources.unwrap_or(false),
+    );
+    if let Some(source_mirror) = matches.value_of("source-mirror") {
+        options = options.source_mirror(source_mirror);
+    } else if let Some(source_mirror) = &config.source_mirror {
+        options = options.source_mirror(source_mirror);
+    }
+
+    // the audit ledger
+    if let Some(audit_file) = audit_file(matches, config) {
+        options = options.audit_file(audit_file);
+    }
+
+    // weights of the risk score (the command-line ones override the configuration)
+    options = options.risk_weights(config.risk_weights.clone());
+    if let Some(risk_weights) = matches.values_of("risk-weight") {
+        for risk_weight in risk_weights {
+            let weight = risk_weight
+                .split_once('=')
+                .and_then(|(metric, weight)| Some((metric, weight.parse::<f64>().ok()?)))
+                .filter(|(_, weight)| *weight >= 0.0);
+            match weight {
+                Some(weight) => options = options.risk_weights(vec![weight]),
+                None => {
+                    return Err(format!(
+                        "wrong risk-weight {}, must be of the form metric=weight",
+                        risk_weight
+                    ))
+                }
+            }
+        }
+    }
+
+    Ok(options)
 }
 
 //
@@ -44,22 +432,7 @@ fn main() {
         .version("1.0")
         .author("David W. <davidwg@fb.com>")
         .about("Risk management for third-party dependencies")
-        .arg(
-            Arg::with_name("manifest-path")
-                .help("Sets the path to the Cargo.toml to analyze")
-                .short("m")
-                .long("manifest-path")
-                .takes_value(true)
-                .value_name("PATH"),
-        )
-        .arg(
-            Arg::with_name("package")
-                .short("p")
-                .multiple(true)
-                .takes_value(true)
-                .value_name("PACKAGE")
-                .help("can be used to specify exactly which packages in a workspace to use"),
-        )
+        .args(&analysis_args())
         .arg(
             Arg::with_name("html-output")
                 .help("prints the output as HTML (default JSON)")
@@ -68,49 +441,161 @@ fn main() {
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("github-token")
-                .long("github-token")
-                .takes_value(true)
-                .value_name("USER:TOKEN")
-                .help("allows the CLI to retrieve github repos stats"),
-        )
-        .arg(
-            Arg::with_name("proxy")
-                .long("proxy")
+            Arg::with_name("format")
+                .long("format")
                 .takes_value(true)
-                .value_name("PROTOCOL://IP:PORT")
-                .help("uses a proxy to make external requests to github"),
+                .possible_values(&["json", "dot"])
+                .conflicts_with("html-output")
+                .help("prints the output as JSON or as a graphviz graph (default JSON)"),
         )
         .arg(
-            Arg::with_name("ignore-workspace")
-                .short("i")
-                .multiple(true)
+            Arg::with_name("color-by")
+                .long("color-by")
                 .takes_value(true)
-                .value_name("CRATE_NAME")
-                .conflicts_with("package")
-                .help("can be used multiple times to list workplace crates to ignore"),
+                .possible_values(&["risk", "unsafe"])
+                .default_value("risk")
+                .help("colors the packages of the graph by risk score or lines of unsafe code"),
         )
         .arg(
-            Arg::with_name("quiet")
-                .short("q")
-                .help("suppress any output to stdout"),
+            Arg::with_name("json-schema")
+                .long("json-schema")
+                .help("prints the JSON schema of the report and exits"),
         )
         // cargo install cargo-dephell won't work without this
         .arg(Arg::with_name("catch-cargo-cli-bug"))
+        .subcommand(
+            SubCommand::with_name("check")
+                .about("Checks the dependencies against a policy (exits with 1 on violations)")
+                .args(&analysis_args())
+                .args(&policy_args()),
+        )
+        .subcommand(
+            SubCommand::with_name("diff")
+                .about("Compares two JSON reports, or the current code with a git reference")
+                .args(&analysis_args())
+                .arg(
+                    Arg::with_name("git-ref")
+                        .long("git-ref")
+                        .takes_value(true)
+                        .value_name("REF")
+                        .help(
+                            "compares with the code at this git reference (branch, tag or commit)",
+                        ),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["terminal", "markdown", "json"])
+                        .default_value("terminal")
+                        .help("the output format (markdown can be posted on a pull request)"),
+                )
+                .arg(
+                    Arg::with_name("reports")
+                        .multiple(true)
+                        .value_name("REPORT")
+                        .help("the OLD and NEW JSON reports (only NEW, or none, with --git-ref)"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("audit")
+                .about("Records the audit of a dependency in the audit ledger (AUDIT.toml)")
+                .args(&input_args())
+                .arg(
+                    Arg::with_name("crate")
+                        .required(true)
+                        .value_name("CRATE")
+                        .help("the name of the crate audited"),
+                )
+                .arg(
+                    Arg::with_name("crate-version")
+                        .value_name("VERSION")
+                        .required_unless("commit")
+                        .help("the version audited"),
+                )
+                .arg(
+                    Arg::with_name("commit")
+                        .long("commit")
+                        .takes_value(true)
+                        .value_name("SHA")
+                        .help("the commit of the crate's repository audited"),
+                )
+                .arg(
+                    Arg::with_name("reviewer")
+                        .long("reviewer")
+                        .required(true)
+                        .takes_value(true)
+                        .value_name("NAME")
+                        .help("who audited the crate"),
+                )
+                .arg(
+                    Arg::with_name("date")
+                        .long("date")
+                        .takes_value(true)
+                        .value_name("YYYY-MM-DD")
+                        .help("when the crate was audited (default today)"),
+                )
+                .arg(
+                    Arg::with_name("scope")
+                        .long("scope")
+                        .takes_value(true)
+                        .possible_values(&["full", "partial"])
+                        .default_value("full")
+                        .help("was the whole crate audited, or only some parts of it"),
+                )
+                .arg(
+                    Arg::with_name("notes")
+                        .long("notes")
+                        .takes_value(true)
+                        .value_name("TEXT")
+                        .help("anything worth knowing about the audit"),
+                ),
+        )
         .get_matches();
 
-    // get metadata from manifest path
-    let manifest_path = matches
-        .value_of("manifest-path")
-        .map(|s| s.to_owned())
-        .unwrap_or_else(|| {
-            let mut current_dir = PathBuf::from(std::env::current_dir().unwrap());
-            current_dir.push("Cargo.toml");
-            current_dir.to_str().unwrap().to_owned()
-        });
+    // print the JSON schema of the report (if asked)
+    if matches.is_present("json-schema") {
+        println!(
+            "{}",
+            serde_json::to_string_pretty(&report_schema()).unwrap()
+        );
+        return;
+    }
+
+    match matches.subcommand() {
+        ("check", Some(matches)) => check(matches),
+        ("diff", Some(matches)) => diff(matches),
+        ("audit", Some(matches)) => audit(matches),
+        _ => report(&matches),
+    }
+}
+
+/// analyzes and outputs the report as JSON or HTML
+fn report(matches: &ArgMatches) {
+    let (manifest_path, config) = match read_config(matches) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
+        }
+    };
+
+    // output as HTML (if given), unless another format is asked
+    let format = matches.value_of("format");
+    let html_output = matches
+        .value_of("html-output")
+        .map(PathBuf::from)
+        .or_else(|| config.output.html.clone())
+        .filter(|_| format.is_none());
+    let color_by = match matches.value_of("color-by") {
+        Some("unsafe") => ColorBy::UnsafeLoc,
+        _ => ColorBy::Risk,
+    };
 
     // quiet if wanted, or JSON
-    let quiet = matches.is_present("quiet") || !matches.is_present("html-output");
+    let quiet = matches.is_present("quiet")
+        || config.output.quiet.unwrap_or(false)
+        || html_output.is_none();
 
     // pretty hello world :>
     if !quiet {
@@ -120,50 +605,17 @@ fn main() {
         println!("  please wait, this can take a while...\n");
     }
 
-    // parse github token (if given)
-    let github_token = matches.value_of("github-token").and_then(|github_token| {
-        let github_token: Vec<&str> = github_token.split(":").collect();
-        if github_token.len() != 2 {
-            eprintln!("wrong github-token, must be of the form username:token");
-            return None;
+    // configure the analysis
+    let options = match analysis_options(matches, &manifest_path, &config, quiet) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            return;
         }
-        let username = github_token[0];
-        let token = github_token[1];
-        Some((username, token))
-    });
-
-    // create an HTTP client (used for example to query github API to get # of stars)
-    let mut http_client = reqwest::blocking::ClientBuilder::new().user_agent("mimoo/cargo-dephell");
-    if let Some(proxy) = matches.value_of("proxy") {
-        let reqwest_proxy = match reqwest::Proxy::all(proxy) {
-            Ok(x) => x,
-            Err(err) => {
-                eprintln!("{}", err);
-                return;
-            }
-        };
-        http_client = http_client.proxy(reqwest_proxy);
-    }
-    let http_client = http_client.build().unwrap();
-
-    // parse dependencies to ignore
-    let to_ignore = matches.values_of("ignore-workspace");
-    let to_ignore: Option<Vec<&str>> = to_ignore.map(|x| x.collect());
-
-    // parse packages to use
-    let packages = matches.values_of("package");
-    let packages: Option<Vec<&str>> = packages.map(|x| x.collect());
+    };
 
     // do the analysis
-    let result = analysis::analyze_repo(
-        &manifest_path,
-        http_client,
-        github_token,
-        packages,
-        to_ignore,
-        quiet,
-    );
-    let (root_crates, main_dependencies, analysis_result) = match result {
+    let report = match analyze_repo(&options) {
         Err(err) => {
             eprintln!("{}", err);
             return;
@@ -172,22 +624,20 @@ fn main() {
     };
 
     // convert result to JSON
-    let json_result = JsonResult {
-        root_crates,
-        main_dependencies,
-        analysis_result,
-    };
-    let json_result = serde_json::to_string(&json_result).unwrap();
+    let json_result = serde_json::to_string(&report).unwrap();
 
     // print out result
     use std::fs::File;
     use std::io::prelude::*;
-    match matches.value_of("html-output") {
+    match html_output {
+        None if format == Some("dot") => {
+            print!("{}", to_dot(&report, color_by));
+        }
         None => {
             println!("{}", json_result);
         }
         Some(html_output) => {
-            let name = std::path::Path::new(&manifest_path)
+            let name = Path::new(&manifest_path)
                 .parent()
                 .unwrap()
                 .file_name()
@@ -196,21 +646,208 @@ fn main() {
                 .unwrap()
                 .to_owned();
             let html_page = HtmlList {
-                name: name,
+                name,
                 json_result: base64::encode(json_result),
+                dot_graph: base64::encode(to_dot(&report, color_by)),
             };
-            let mut file = match File::create(html_output) {
+            let mut file = match File::create(&html_output) {
                 Ok(x) => x,
                 Err(err) => {
                     eprintln!("{}", err);
                     return;
                 }
             };
-            let _ = write!(&mut file, "{}", html_page.render().unwrap()).unwrap();
+            write!(&mut file, "{}", html_page.render().unwrap()).unwrap();
             if !quiet {
-                println!("\n=> html output saved at {}", html_output);
+                println!("\n=> html output saved at {}", html_output.display());
             }
         }
     };
-    //
+}
+
+/// analyzes and checks the dependencies against the policy,
+/// exits with 1 if the policy is violated (or 2 if the analysis failed)
+fn check(matches: &ArgMatches) {
+    let (manifest_path, config) = match read_config(matches) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    // the policy (command-line flags take precedence over the configuration)
+    let mut policy = config.policy.clone();
+    let parse_threshold = |name: &str| {
+        matches.value_of(name).map(|value| {
+            value.parse().unwrap_or_else(|_| {
+                eprintln!("wrong {}, must be a positive number", name);
+                std::process::exit(2);
+            })
+        })
+    };
+    if let Some(max) = parse_threshold("max-total-unsafe-loc") {
+        policy.max_total_unsafe_loc = Some(max);
+    }
+    if let Some(max) = parse_threshold("max-transitive-deps") {
+        policy.max_transitive_deps = Some(max as usize);
+    }
+    if let Some(min) = parse_threshold("min-crates-io-dependent") {
+        policy.min_crates_io_dependent = Some(min);
+    }
+    if let Some(max) = parse_threshold("max-days-since-update") {
+        let max = i64::try_from(max).unwrap_or_else(|_| {
+            eprintln!("wrong max-days-since-update, must be at most {}", i64::MAX);
+            std::process::exit(2);
+        });
+        policy.max_days_since_update = Some(max);
+    }
+    if matches.is_present("forbid-duplicate-versions") {
+        policy.forbid_duplicate_versions = Some(true);
+    }
+    if matches.is_present("forbid-advisories") {
+        policy.forbid_advisories = Some(true);
+    }
+    if matches.is_present("forbid-unmaintained") {
+        policy.forbid_unmaintained = Some(true);
+    }
+    if matches.is_present("forbid-yanked") {
+        policy.forbid_yanked = Some(true);
+    }
+    if policy.is_empty() {
+        eprintln!("dephell: no policy to check, see the [policy] section of the configuration");
+    }
+
+    // do the analysis
+    let options = match analysis_options(matches, &manifest_path, &config, true) {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let report = match analyze_repo(&options) {
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+        Ok(x) => x,
+    };
+
+    // check the policy
+    let result = check_policy(&report, &policy);
+    for (rule, reason) in &result.not_evaluated {
+        eprintln!("dephell: {} was not checked: {}", rule, reason);
+    }
+    if result.violations.is_empty() {
+        if !matches.is_present("quiet") {
+            println!("dephell: no policy violation");
+        }
+        return;
+    }
+    println!("dephell: {} policy violation(s)", result.violations.len());
+    for violation in &result.violations {
+        println!("- {}", violation);
+    }
+    std::process::exit(1);
+}
+
+/// compares two reports (or the current code with a git reference)
+fn diff(matches: &ArgMatches) {
+    let reports: Vec<&str> = matches
+        .values_of("reports")
+        .map(|reports| reports.collect())
+        .unwrap_or_default();
+
+    // obtain the old and new reports
+    let old_and_new = match (matches.value_of("git-ref"), reports.as_slice()) {
+        (None, [old, new]) => read_report(Path::new(old))
+            .and_then(|old| read_report(Path::new(new)).map(|new| (old, new))),
+        (Some(git_ref), [] | [_]) => read_config(matches).and_then(|(manifest_path, config)| {
+            let options = match analysis_options(matches, &manifest_path, &config, true) {
+                Ok(options) => options,
+                Err(err) => {
+                    eprintln!("{}", err);
+                    std::process::exit(2);
+                }
+            };
+            let old = analyze_git_ref(&options, git_ref)?;
+            let new = match reports.first() {
+                Some(new) => read_report(Path::new(new))?,
+                None => analyze_repo(&options)?,
+            };
+            Ok((old, new))
+        }),
+        _ => {
+            eprintln!("dephell: diff takes OLD and NEW reports, or --git-ref and a NEW report");
+            std::process::exit(2);
+        }
+    };
+    let (old, new) = match old_and_new {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+
+    // output the difference
+    let report_diff = diff_reports(&old, &new);
+    match matches.value_of("format") {
+        Some("markdown") => print!("{}", report_diff.to_markdown()),
+        Some("json") => println!("{}", serde_json::to_string(&report_diff).unwrap()),
+        _ => print!("{}", report_diff.to_terminal()),
+    }
+}
+
+/// appends an audit to the audit ledger
+fn audit(matches: &ArgMatches) {
+    let (manifest_path, config) = match read_config(matches) {
+        Ok(x) => x,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    };
+    let audit_file = audit_file(matches, &config).unwrap_or_else(|| {
+        let manifest_dir = Path::new(&manifest_path)
+            .parent()
+            .unwrap_or_else(|| Path::new(""));
+        manifest_dir.join(AUDIT_FILE_NAME)
+    });
+
+    // the audit
+    let date = match matches.value_of("date").map(str::parse) {
+        Some(Ok(date)) => date,
+        Some(Err(_)) => {
+            eprintln!("wrong date, must be of the form YYYY-MM-DD");
+            std::process::exit(2);
+        }
+        None => Local::today().naive_local(),
+    };
+    let audit = Audit {
+        crate_name: matches.value_of("crate").unwrap().to_string(),
+        version: matches.value_of("crate-version").map(String::from),
+        commit: matches.value_of("commit").map(String::from),
+        reviewer: matches.value_of("reviewer").unwrap().to_string(),
+        date,
+        scope: match matches.value_of("scope") {
+            Some("partial") => AuditScope::Partial,
+            _ => AuditScope::Full,
+        },
+        notes: matches.value_of("notes").map(String::from),
+    };
+
+    // append it (after making sure the ledger is valid)
+    if audit_file.is_file() {
+        if let Err(err) = AuditLedger::from_file(&audit_file) {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
+    }
+    if let Err(err) = AuditLedger::append(&audit_file, &audit) {
+        eprintln!("{}", err);
+        std::process::exit(2);
+    }
+    println!("dephell: audit recorded in {}", audit_file.display());
 }
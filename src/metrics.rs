@@ -1,12 +1,16 @@
 use camino::Utf8Path;
-use guppy::graph::{DependencyDirection, PackageGraph, PackageMetadata};
+use chrono::{DateTime, FixedOffset, NaiveDate};
 use guppy::PackageId;
 use serde::Deserialize;
-use std::collections::HashSet;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use crate::analysis::PackageRisk;
+use crate::analysis::{package_key, FileMetrics, PackageRisk};
+use crate::modules::ModuleFiles;
+use crate::resolve::ResolvedGraph;
+use crate::unsafety::{self, UnsafeMetrics};
+use crate::Error;
 
 //
 // Analysis Functions
@@ -175,10 +179,10 @@ pub fn get_crates_io_dependent(
 pub fn get_crates_io_last_updated(
     http_client: reqwest::blocking::Client,
     crate_name: &str,
-) -> Option<String> {
+) -> Option<NaiveDate> {
     #[derive(Deserialize, Debug)]
     struct Crate {
-        updated_at: String,
+        updated_at: DateTime<FixedOffset>,
     }
     #[derive(Deserialize, Debug)]
     pub struct Response {
@@ -209,101 +213,278 @@ pub fn get_crates_io_last_updated(
             eprintln!("dephell: {}", err);
             None
         }
-        Ok(resp) => {
-            let updated_at = resp.crate_.updated_at;
-            let formatted_date = chrono::DateTime::parse_from_rfc3339(&updated_at)
-                .unwrap()
-                .format("%Y-%m-%d")
-                .to_string();
-            Some(formatted_date)
+        Ok(resp) => Some(resp.crate_.updated_at.date().naive_local()),
+    }
+}
+
+/// is this version of a crate yanked from crates.io?
+pub fn get_crates_io_yanked(
+    http_client: reqwest::blocking::Client,
+    crate_name: &str,
+    version: &str,
+) -> Option<bool> {
+    #[derive(Deserialize, Debug)]
+    struct Version {
+        yanked: bool,
+    }
+    #[derive(Deserialize, Debug)]
+    pub struct Response {
+        version: Version,
+    }
+    // create request to crates.io API
+    let request_url = format!("https://crates.io/api/v1/crates/{}/{}", crate_name, version);
+    let request = http_client.get(&request_url);
+    // send the request
+    let resp = match request.send() {
+        Err(err) => {
+            eprintln!("{}", err);
+            return None;
+        }
+        Ok(resp) => resp,
+    };
+    // parse response
+    if !resp.status().is_success() {
+        eprintln!("dephell: crates.io request failed");
+        eprintln!("query: {}", request_url);
+        eprintln!("status: {}", resp.status());
+        eprintln!("text: {:?}", resp.text());
+        return None;
+    }
+    let resp: reqwest::Result<Response> = resp.json();
+    match resp {
+        Err(err) => {
+            eprintln!("dephell: {}", err);
+            None
         }
+        Ok(resp) => Some(resp.version.yanked),
     }
 }
 
+/// VcsInfo is the content of the `.cargo_vcs_info.json` file that cargo includes
+/// in the published crates (when they are published from a git repository)
+#[derive(Deserialize, Debug)]
+pub struct VcsInfo {
+    pub git: VcsGitInfo,
+    /// the folder of the crate in its repository (empty for the root of the repository,
+    /// missing for crates published before cargo 1.58)
+    #[serde(default)]
+    pub path_in_vcs: String,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct VcsGitInfo {
+    /// the commit the crate was published from
+    pub sha1: String,
+}
+
+/// reads the `.cargo_vcs_info.json` of a package, if any
+pub fn get_vcs_info(manifest_path: &Utf8Path) -> Option<VcsInfo> {
+    let vcs_info_path = manifest_path.parent()?.join(".cargo_vcs_info.json");
+    let content = fs::read_to_string(vcs_info_path).ok()?;
+    serde_json::from_str(&content).ok()
+}
+
 /// obtains all root crates that end up importing this dependency
 pub fn get_root_importers(
-    package_graph: &PackageGraph,
+    resolved: &ResolvedGraph,
     root_crates: &HashSet<PackageId>,
     dependency: &PackageId,
 ) -> Vec<String> {
-    let root_importers = package_graph
-        .query_reverse(std::iter::once(dependency))
-        .unwrap();
-    let root_importers = root_importers.resolve();
-    let root_importers = root_importers.packages(DependencyDirection::Reverse);
-    let root_importers: Vec<PackageMetadata> = root_importers
-        .filter(|pkg_metadata| root_crates.contains(&pkg_metadata.id())) // a root crate is an importer
-        .collect();
-    let root_importers = root_importers
+    let mut root_importers: Vec<String> = root_crates
         .iter()
-        .map(|pkg_metadata| pkg_metadata.name().to_string())
+        .filter(|root_crate| {
+            resolved
+                .transitive_dependencies(root_crate)
+                .contains(dependency)
+        }) // a root crate is an importer
+        .map(|root_crate| resolved.metadata(root_crate).name().to_string())
         .collect();
+    root_importers.sort();
     root_importers
 }
 
 /// obtains all the dependencies that are introduced by this dependency, and this dependency only
 pub fn get_exclusive_deps(
-    package_graph: &PackageGraph,
-    root_crates: &HashSet<PackageId>,
+    resolved: &ResolvedGraph,
     dependency: &PackageId,
-) -> Vec<String> {
+) -> Result<Vec<String>, Error> {
     // get all the transitive dependencies of `dependency`
-    let transitive_deps = package_graph
-        .query_forward(std::iter::once(dependency))
-        .unwrap();
-    let transitive_deps = transitive_deps.resolve();
-    let transitive_deps = transitive_deps.packages(DependencyDirection::Forward);
-
-    // re-create a graph without edges leading to our dependency (and its tree)
-    let package_graph = package_graph.clone();
-    package_graph
-        .query_forward(root_crates.iter())
-        .unwrap()
-        .resolve_with_fn(|_, link| !(link.to().id() == dependency));
+    let transitive_deps = resolved.transitive_dependencies(dependency);
 
-    // obtain all dependencies from the root_crates
-    let new_all = package_graph.query_forward(root_crates.iter()).unwrap();
-    let new_all: Vec<_> = new_all
-        .resolve()
-        .package_ids(DependencyDirection::Forward)
-        .collect();
+    // obtain all dependencies from the root crates,
+    // as if no one was importing our dependency
+    let new_all = resolved.without(dependency)?;
 
     // check if the original transitive dependencies are in there
     let mut exclusive_deps = Vec::new();
     for transitive_dep in transitive_deps {
-        // don't include the dependency itself in this list
-        if transitive_dep.id() == dependency {
-            continue;
-        }
         // if it's not in the new graph, it's exclusive to our dependency!
-        if !new_all.contains(&transitive_dep.id()) {
-            exclusive_deps.push(transitive_dep.name().to_string());
+        if !new_all.contains(transitive_dep) {
+            exclusive_deps.push(package_key(&resolved.metadata(transitive_dep)));
         }
     }
 
     //
-    exclusive_deps
+    exclusive_deps.sort();
+    Ok(exclusive_deps)
+}
+
+/// obtains, for each root crate importing this dependency, one of the shortest paths
+/// of packages (root crate first, dependency last) leading to the dependency
+pub fn get_import_paths(
+    resolved: &ResolvedGraph,
+    root_crates: &HashSet<PackageId>,
+    dependency: &PackageId,
+) -> Vec<Vec<String>> {
+    let mut paths = Vec::new();
+    for root_crate in root_crates {
+        // breadth-first search from the root crate, remembering how we got to each package
+        let mut parents: HashMap<&PackageId, &PackageId> = HashMap::new();
+        let mut to_visit = VecDeque::new();
+        to_visit.push_back(root_crate);
+        while let Some(package_id) = to_visit.pop_front() {
+            if package_id == dependency {
+                break;
+            }
+            for dep_link in resolved.direct_links(package_id) {
+                let dep_id = dep_link.to().id();
+                if dep_id != root_crate && !parents.contains_key(dep_id) {
+                    parents.insert(dep_id, package_id);
+                    to_visit.push_back(dep_id);
+                }
+            }
+        }
+
+        // this root crate doesn't import the dependency
+        if !parents.contains_key(dependency) {
+            continue;
+        }
+
+        // walk back from the dependency to the root crate
+        let mut path = vec![dependency];
+        while let Some(parent) = parents.get(path.last().unwrap()) {
+            path.push(parent);
+        }
+        let path = path
+            .into_iter()
+            .rev()
+            .map(|package_id| package_key(&resolved.metadata(package_id)))
+            .collect();
+        paths.push(path);
+    }
+    paths.sort();
+    paths
 }
 
 /// counts the lines-of-code of all the given files
-pub fn get_loc(package_risk: &mut PackageRisk, dependency_files: &HashSet<String>) {
+pub fn get_loc(
+    package_risk: &mut PackageRisk,
+    dependency_files: &HashSet<String>,
+    module_files: &ModuleFiles,
+) {
     for dependency_file in dependency_files {
         // look for all lines of code (not just rust)
         let lang = loc::lang_from_ext(dependency_file);
         if lang != loc::Lang::Unrecognized {
-            let count = loc::count(dependency_file);
+            let mut count = u64::from(loc::count(dependency_file).code);
+            // the lines disabled by `#[cfg(...)]` attributes (like unit tests) are not compiled
+            if let Some(disabled_lines) = module_files.disabled_lines(dependency_file) {
+                count = count.saturating_sub(count_code_lines(dependency_file, disabled_lines));
+            }
             // update LOC
             // TODO: compute the .loc from all files, not from .d
-            package_risk.loc += u64::from(count.code);
-            if lang == loc::Lang::Rust {
-                package_risk.rust_loc += u64::from(count.code);
-            }
+            let rust_count = if lang == loc::Lang::Rust { count } else { 0 };
+            let file_metrics = get_file_metrics(package_risk, dependency_file);
+            file_metrics.loc += count;
+            file_metrics.rust_loc += rust_count;
+            package_risk.loc += count;
+            package_risk.rust_loc += rust_count;
         }
     }
 }
 
+/// counts the given lines of a file that are neither blank nor line comments
+fn count_code_lines(file: &str, lines: &BTreeSet<usize>) -> u64 {
+    if lines.is_empty() {
+        return 0;
+    }
+    let content = fs::read_to_string(file).unwrap_or_default();
+    content
+        .lines()
+        .enumerate()
+        .filter(|(index, line)| {
+            let line = line.trim();
+            lines.contains(&(index + 1)) && !line.is_empty() && !line.starts_with("//")
+        })
+        .count() as u64
+}
+
+/// the metrics of a file of a package, keyed by its path relative to the package folder
+/// (files generated in the target directory keep their absolute path)
+fn get_file_metrics<'a>(package_risk: &'a mut PackageRisk, file: &str) -> &'a mut FileMetrics {
+    let package_path = package_risk.manifest_path.parent().unwrap();
+    let key = match Path::new(file).strip_prefix(package_path) {
+        Ok(relative) => relative.to_string_lossy().into_owned(),
+        Err(_) => file.to_string(),
+    };
+    package_risk.files.entry(key).or_default()
+}
+
+/// finds the unsafe code of the rust files listed in dep-info files
+pub fn get_unsafe_metrics(package_risk: &mut PackageRisk, dependency_files: &HashSet<String>) {
+    for dependency_file in dependency_files {
+        if loc::lang_from_ext(dependency_file) == loc::Lang::Rust {
+            let mut unsafe_metrics = UnsafeMetrics::default();
+            let unsafe_loc = unsafety::analyze_file(dependency_file, &mut unsafe_metrics);
+            package_risk.unsafe_loc += unsafe_loc;
+            package_risk.unsafe_metrics.add(&unsafe_metrics);
+            let file_metrics = get_file_metrics(package_risk, dependency_file);
+            file_metrics.unsafe_loc += unsafe_loc;
+            file_metrics.unsafe_metrics.add(&unsafe_metrics);
+        }
+    }
+}
+
+/// the languages of the native code that build scripts compile
+const NATIVE_LANGS: [loc::Lang; 6] = [
+    loc::Lang::C,
+    loc::Lang::CCppHeader,
+    loc::Lang::Cpp,
+    loc::Lang::ObjectiveC,
+    loc::Lang::ObjectiveCpp,
+    loc::Lang::Assembly,
+];
+
+/// counts the lines-of-code of the native code vendored in a package (it is compiled by the
+/// build script, so it doesn't appear in dep-info files and is added to the `.loc` as well)
+pub fn get_native_loc(package_risk: &mut PackageRisk, dependency_files: &HashSet<String>) {
+    let package_path = package_risk.manifest_path.parent().unwrap();
+    for file in get_every_file_in_folder(package_path) {
+        if !NATIVE_LANGS.contains(&loc::lang_from_ext(&file)) {
+            continue;
+        }
+        let count = u64::from(loc::count(&file).code);
+        package_risk.native_loc += count;
+        if !dependency_files.contains(&file) {
+            package_risk.loc += count;
+            get_file_metrics(package_risk, &file).loc += count;
+        }
+    }
+}
+
+/// is this a -sys crate? (a crate binding to a native library)
+pub fn is_sys_crate(package_risk: &PackageRisk) -> bool {
+    package_risk.links.is_some()
+        || package_risk.name.ends_with("-sys")
+        || package_risk.name.ends_with("_sys")
+        || package_risk
+            .native_libraries
+            .as_ref()
+            .is_some_and(|libraries| !libraries.is_empty())
+}
+
 /// parses the dep-info files that contain all the files relevant to the compilation of a dependency (these files are like Makefiles)
-// TODO: what to do about libraries linked via bindings
+/// (native code compiled by build scripts is not listed, see `get_native_loc`)
 fn parse_rustc_dep_info(rustc_dep_info: &Path) -> HashSet<String> {
     let contents = fs::read_to_string(rustc_dep_info).unwrap();
     // inspired from https://github.com/rust-lang/cargo/blob/13cd4fb1e8be5b8fb44008052cf31a839d745a45/src/cargo/core/compiler/fingerprint.rs#L1646
@@ -351,34 +532,202 @@ fn get_every_file_in_folder(package_path: &Utf8Path) -> HashSet<String> {
     dependency_files
 }
 
-/// obtains a dependency's files (might be accurate or not)
+/// obtains a dependency's files (might be accurate or not).
+/// The relative paths of dep-info files are relative to `rustc_dir`, the folder cargo runs rustc in:
+/// the workspace root for path dependencies, and the package root otherwise.
 pub fn get_dependency_files(
     package_name: &str,
     manifest_path: &Utf8Path,
+    rustc_dir: &Utf8Path,
     target_dir: &Path,
+    targets: &[String],
+    dep_info_files: &HashSet<PathBuf>,
+    module_files: &ModuleFiles,
 ) -> (bool, HashSet<String>) {
     use glob::glob;
 
-    // find the dep-info file for that dependency
-    let mut dep_files_path = target_dir.to_path_buf();
-    dep_files_path.push("debug/deps");
-    let without_underscore_name = package_name.replace("-", "_");
+    // dep-info files live in `debug/deps` for the host (this includes build dependencies
+    // and proc macros), and in `<triple>/debug/deps` for each target we built for
+    let mut deps_dirs = vec![target_dir.join("debug/deps")];
+    for target in targets {
+        deps_dirs.push(target_dir.join(target).join("debug/deps"));
+    }
+
+    // find the dep-info files for that dependency
+    // (several versions of the same dependency can have dep-info files, so we look for
+    // the ones listing files of this version)
+    let package_path = manifest_path.parent().unwrap();
+    let without_underscore_name = package_name.replace('-', "_");
     let dependency_file = format!("{}-*.d", without_underscore_name);
-    dep_files_path.push(dependency_file);
-    let dep_files_path = glob(dep_files_path.to_str().unwrap()).unwrap().next();
-    match dep_files_path {
-        // we found a dep-info file
-        Some(glob_result) => {
-            let dep_files_path = glob_result.unwrap();
-            let dependency_files = parse_rustc_dep_info(dep_files_path.as_path());
-            (true, dependency_files)
-        }
-        // this dependency is not part of our target+features: let's do it the old fashion way
-        None => {
-            // eprintln!("dephell: no dep-info file found for {}", package_name);
-            let package_path = manifest_path.parent().unwrap();
-            let dependency_files = get_every_file_in_folder(package_path);
-            (false, dependency_files)
+    let mut found = false;
+    let mut dependency_files = HashSet::new();
+    for deps_dir in deps_dirs {
+        let dep_files_path = deps_dir.join(&dependency_file);
+        for dep_files_path in glob(dep_files_path.to_str().unwrap()).unwrap() {
+            let dep_files_path = dep_files_path.unwrap();
+            // (the dep-info files of crates that were not checked this time are ignored)
+            if !dep_info_files.contains(&dep_files_path) {
+                continue;
+            }
+            // (relative files outside of the package, like the manifest of the workspace
+            // listed by clippy for a workspace member, are not part of the dependency)
+            let files: HashSet<String> = parse_rustc_dep_info(dep_files_path.as_path())
+                .into_iter()
+                .filter_map(|file| {
+                    if Path::new(&file).is_absolute() {
+                        return Some(file);
+                    }
+                    let file = rustc_dir.join(file);
+                    file.starts_with(package_path).then(|| file.into_string())
+                })
+                .collect();
+            // (only the rust files tell which version it is for: the manifest listed by clippy
+            // is relative, so it appears to be in the package whatever the version)
+            if files
+                .iter()
+                .any(|file| file.ends_with(".rs") && Path::new(file).starts_with(package_path))
+            {
+                found = true;
+                dependency_files.extend(files);
+            }
         }
     }
+
+    // we found a dep-info file
+    if found {
+        return (true, dependency_files);
+    }
+
+    // this dependency is not part of our target+features: let's follow its module tree
+    (false, module_files.paths())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::FeatureSelection;
+    use guppy::MetadataCommand;
+    use tempdir::TempDir;
+
+    /// writes a crate named `name` with the given dependencies sections in `dir`
+    fn write_crate(dir: &Path, name: &str, dependencies: &str) {
+        let crate_dir = dir.join(name);
+        fs::create_dir_all(crate_dir.join("src")).unwrap();
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n{}",
+            name, dependencies
+        );
+        fs::write(crate_dir.join("Cargo.toml"), manifest).unwrap();
+        fs::write(crate_dir.join("src/lib.rs"), "").unwrap();
+    }
+
+    #[test]
+    fn exclusive_deps() {
+        // app -> a -> c -> d
+        // app -> b -> d
+        // app -> c (dev dependency)
+        let dir = TempDir::new("dephell_metrics").unwrap();
+        write_crate(
+            dir.path(),
+            "app",
+            "[dependencies]\na = { path = \"../a\" }\nb = { path = \"../b\" }\n\n\
+             [dev-dependencies]\nc = { path = \"../c\" }\n",
+        );
+        write_crate(dir.path(), "a", "[dependencies]\nc = { path = \"../c\" }\n");
+        write_crate(dir.path(), "b", "[dependencies]\nd = { path = \"../d\" }\n");
+        write_crate(dir.path(), "c", "[dependencies]\nd = { path = \"../d\" }\n");
+        write_crate(dir.path(), "d", "");
+
+        let mut cmd = MetadataCommand::new();
+        cmd.manifest_path(dir.path().join("app/Cargo.toml"));
+        let package_graph = cmd.build_graph().unwrap();
+        let package_id = |name: &str| {
+            package_graph
+                .packages()
+                .find(|package| package.name() == name)
+                .unwrap()
+                .id()
+                .clone()
+        };
+        let root_crates: HashSet<PackageId> = std::iter::once(package_id("app")).collect();
+
+        let resolved = ResolvedGraph::new(
+            &package_graph,
+            &root_crates,
+            &FeatureSelection::default(),
+            &[],
+            false,
+        )
+        .unwrap();
+
+        // c is only pulled by a (dev dependencies are not part of the build),
+        // while d is also pulled by b
+        let exclusive = |name: &str| get_exclusive_deps(&resolved, &package_id(name)).unwrap();
+        assert_eq!(exclusive("a"), ["c 0.1.0"]);
+        assert!(exclusive("b").is_empty());
+        assert!(exclusive("c").is_empty());
+    }
+
+    #[test]
+    fn dependency_files_stay_in_the_package() {
+        // workspace/Cargo.toml (root package)
+        // workspace/sub (workspace member)
+        // registry/dep-1 and registry/dep-2 (two versions of a registry dependency)
+        let dir = TempDir::new("dephell_metrics").unwrap();
+        let dir = Utf8Path::from_path(dir.path()).unwrap();
+        let workspace_root = dir.join("workspace");
+        let dep_root = dir.join("registry/dep-1");
+        let dep_2_root = dir.join("registry/dep-2");
+        let target_dir = dir.join("target");
+        fs::create_dir_all(target_dir.join("debug/deps")).unwrap();
+
+        // rustc runs in the workspace root for path dependencies, and in the package root
+        // otherwise (clippy adds the relative path of the manifest)
+        let sub_dep_info = target_dir.join("debug/deps/sub-0123.d");
+        fs::write(&sub_dep_info, "sub-0123.rmeta: sub/src/lib.rs Cargo.toml\n").unwrap();
+        let dep_dep_info = target_dir.join("debug/deps/dep-4567.d");
+        fs::write(
+            &dep_dep_info,
+            format!("dep-4567.rmeta: {}/src/lib.rs Cargo.toml\n", dep_root),
+        )
+        .unwrap();
+        let dep_2_dep_info = target_dir.join("debug/deps/dep-89ab.d");
+        fs::write(
+            &dep_2_dep_info,
+            format!("dep-89ab.rmeta: {}/src/lib.rs Cargo.toml\n", dep_2_root),
+        )
+        .unwrap();
+        let dep_info_files: HashSet<PathBuf> = [&sub_dep_info, &dep_dep_info, &dep_2_dep_info]
+            .iter()
+            .map(|dep_info| PathBuf::from(dep_info.as_str()))
+            .collect();
+        let dependency_files = |name: &str, package_root: &Utf8Path, rustc_dir: &Utf8Path| {
+            let (found, files) = get_dependency_files(
+                name,
+                &package_root.join("Cargo.toml"),
+                rustc_dir,
+                Path::new(target_dir.as_str()),
+                &[],
+                &dep_info_files,
+                &ModuleFiles::default(),
+            );
+            assert!(found);
+            let mut files: Vec<String> = files.into_iter().collect();
+            files.sort();
+            files
+        };
+
+        let sub_root = workspace_root.join("sub");
+        assert_eq!(
+            dependency_files("sub", &sub_root, &workspace_root),
+            [sub_root.join("src/lib.rs").into_string()]
+        );
+        assert_eq!(
+            dependency_files("dep", &dep_root, &dep_root),
+            [
+                dep_root.join("Cargo.toml").into_string(),
+                dep_root.join("src/lib.rs").into_string()
+            ]
+        );
+    }
 }
@@ -0,0 +1,830 @@
+use guppy::graph::{BuildTargetId, PackageMetadata};
+use proc_macro2::{Group, Span, TokenStream, TokenTree};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use syn::ext::IdentExt;
+use syn::parse::ParseStream;
+use syn::punctuated::Punctuated;
+use syn::spanned::Spanned;
+use syn::{braced, Attribute, Item, Meta, Token};
+
+use crate::resolve::DependencyKind;
+use crate::Error;
+
+/// Cfg is the set of cfg options of a target (for example `unix` or `target_os = "linux"`),
+/// against which `#[cfg(...)]` attributes are evaluated.
+pub struct Cfg(HashSet<(String, Option<String>)>);
+
+impl Cfg {
+    /// the cfg options of each target triple (of the host if none is given), as printed by
+    /// `rustc --print cfg`
+    pub fn of_targets(targets: &[String]) -> Result<Vec<Cfg>, Error> {
+        if targets.is_empty() {
+            return Ok(vec![Cfg::host()?]);
+        }
+        targets
+            .iter()
+            .map(|target| Cfg::of_target(Some(target)))
+            .collect()
+    }
+
+    /// the cfg options of the host, for which build scripts and procedural macros are compiled
+    pub fn host() -> Result<Cfg, Error> {
+        Cfg::of_target(None)
+    }
+
+    fn of_target(target: Option<&str>) -> Result<Cfg, Error> {
+        let mut cmd = std::process::Command::new("rustc");
+        cmd.args(["--print", "cfg"]);
+        if let Some(target) = target {
+            cmd.args(["--target", target]);
+        }
+        let output = cmd.output()?;
+        if !output.status.success() {
+            let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+            return Err(Error::Rustc(stderr));
+        }
+        let mut cfg = Cfg::parse(String::from_utf8_lossy(&output.stdout).lines());
+        // dependencies are checked with the dev profile
+        cfg.0.insert(("debug_assertions".to_string(), None));
+        Ok(cfg)
+    }
+
+    /// parses cfg options written like `name` or `name="value"` (the way rustc prints them,
+    /// and build scripts set them with `cargo:rustc-cfg`)
+    pub fn parse<'a>(options: impl IntoIterator<Item = &'a str>) -> Cfg {
+        let options = options
+            .into_iter()
+            .map(|option| match option.split_once('=') {
+                Some((name, value)) => {
+                    (name.to_string(), Some(value.trim_matches('"').to_string()))
+                }
+                None => (option.to_string(), None),
+            })
+            .collect();
+        Cfg(options)
+    }
+}
+
+/// ModuleFiles are the rust files of a package that get compiled, according to its module
+/// tree, with the lines of each file that are disabled by `#[cfg(...)]` attributes.
+#[derive(Default)]
+pub struct ModuleFiles {
+    /// the disabled lines, indexed by file
+    pub files: BTreeMap<String, BTreeSet<usize>>,
+}
+
+impl ModuleFiles {
+    /// the files of the module tree
+    pub fn paths(&self) -> HashSet<String> {
+        self.files.keys().cloned().collect()
+    }
+
+    /// the lines of a file that are disabled on every target (none if the file is not part
+    /// of the module tree)
+    pub fn disabled_lines(&self, file: &str) -> Option<&BTreeSet<usize>> {
+        self.files.get(file)
+    }
+}
+
+/// CrateRoot is the root file of a crate, with the platforms it is compiled for.
+pub struct CrateRoot {
+    /// path to the root file (like `src/lib.rs`)
+    pub path: PathBuf,
+    /// compiled for the target triples
+    pub target: bool,
+    /// compiled for the host (build scripts, procedural macros and build dependencies)
+    pub host: bool,
+}
+
+/// the root files of the targets of a package that get compiled: the library and the build
+/// script, as well as the binaries of the workspace crates (and their tests, examples and
+/// benchmarks if `include_dev` is set). The library is compiled for the host if the package
+/// is a procedural macro or is imported as a build dependency (see `dependency_kinds`).
+pub fn crate_roots(
+    package: &PackageMetadata,
+    dependency_kinds: &BTreeSet<DependencyKind>,
+    include_dev: bool,
+) -> Vec<CrateRoot> {
+    // (dev dependencies are compiled for the target triples, like normal ones)
+    let for_host = dependency_kinds.contains(&DependencyKind::Build);
+    let for_target = dependency_kinds.is_empty()
+        || dependency_kinds
+            .iter()
+            .any(|kind| kind != &DependencyKind::Build);
+    package
+        .build_targets()
+        .filter_map(|target| {
+            let (target_platform, host_platform) = match target.id() {
+                BuildTargetId::BuildScript => (false, true),
+                BuildTargetId::Library if package.is_proc_macro() => (false, true),
+                BuildTargetId::Library => (for_target, for_host),
+                BuildTargetId::Binary(_) if package.in_workspace() => (true, false),
+                BuildTargetId::Test(_)
+                | BuildTargetId::Example(_)
+                | BuildTargetId::Benchmark(_)
+                    if package.in_workspace() && include_dev =>
+                {
+                    (true, false)
+                }
+                _ => return None,
+            };
+            Some(CrateRoot {
+                path: target.path().to_path_buf(),
+                target: target_platform,
+                host: host_platform,
+            })
+        })
+        .collect()
+}
+
+/// walks the module tree of each crate root (following `mod` declarations and `#[path]`
+/// attributes) for each target triple and for the host, with the given features and the cfg
+/// options set by the build script of the package (`script_cfg`) enabled.
+/// Modules disabled by a `#[cfg(...)]` attribute (like `#[cfg(test)]` unit tests) are not
+/// followed. `cfg_if!` invocations, and the macros of the crate invoked without arguments, are
+/// expanded. As modules declared by other macros can't be found, every rust file of the folder
+/// of a crate root is taken if one of its macros declares modules. Modules declared in function
+/// bodies are not found.
+pub fn get_module_files(
+    roots: &[CrateRoot],
+    cfgs: &[Cfg],
+    host_cfg: &Cfg,
+    script_cfg: &Cfg,
+    features: &BTreeSet<String>,
+) -> ModuleFiles {
+    let mut module_files = ModuleFiles::default();
+    let mut incomplete_roots = BTreeSet::new();
+    let walks = cfgs
+        .iter()
+        .map(|cfg| (cfg, false))
+        .chain(std::iter::once((host_cfg, true)));
+    for (cfg, host) in walks {
+        let mut walker = ModuleWalker {
+            cfg,
+            script_cfg,
+            features,
+            files: BTreeMap::new(),
+            macros: HashMap::new(),
+            expanding: Vec::new(),
+            macro_modules: false,
+        };
+        let roots = roots
+            .iter()
+            .filter(|root| if host { root.host } else { root.target });
+        for root in roots {
+            let mod_dir = root.path.parent().unwrap_or_else(|| Path::new(""));
+            walker.macros.clear();
+            walker.macro_modules = false;
+            walker.walk_file(&root.path, mod_dir);
+            if walker.macro_modules {
+                incomplete_roots.insert(mod_dir.to_path_buf());
+            }
+        }
+        // a line is only disabled if it is disabled on every target (and on the host)
+        for (file, disabled) in walker.files {
+            module_files
+                .files
+                .entry(file)
+                .and_modify(|lines| lines.retain(|line| disabled.contains(line)))
+                .or_insert(disabled);
+        }
+    }
+    // the module tree of these roots is incomplete
+    for root_dir in incomplete_roots {
+        for file in ignore::WalkBuilder::new(&root_dir).build().flatten() {
+            if file
+                .path()
+                .extension()
+                .is_some_and(|extension| extension == "rs")
+            {
+                let file = normalize(file.path()).to_string_lossy().into_owned();
+                module_files.files.entry(file).or_default();
+            }
+        }
+    }
+    module_files
+}
+
+struct ModuleWalker<'a> {
+    cfg: &'a Cfg,
+    script_cfg: &'a Cfg,
+    features: &'a BTreeSet<String>,
+    /// the files found so far, with their disabled lines
+    files: BTreeMap<String, BTreeSet<usize>>,
+    /// the tokens of the `macro_rules!` definitions found so far, indexed by name
+    macros: HashMap<String, TokenStream>,
+    /// the macros being expanded
+    expanding: Vec<String>,
+    /// was a macro declaring modules that can't be expanded found?
+    macro_modules: bool,
+}
+
+impl ModuleWalker<'_> {
+    /// walks a file, whose submodules are in `mod_dir`
+    fn walk_file(&mut self, path: &Path, mod_dir: &Path) {
+        let path = normalize(path);
+        let key = path.to_string_lossy().into_owned();
+        if self.files.contains_key(&key) {
+            return;
+        }
+        let file = match fs::read_to_string(&path) {
+            Ok(content) => syn::parse_file(&content),
+            Err(_) => return,
+        };
+        // (files that can't be parsed are counted without following their modules)
+        let file = match file {
+            Ok(file) => file,
+            Err(_) => {
+                self.files.insert(key, BTreeSet::new());
+                return;
+            }
+        };
+        // a file can disable itself with an inner attribute (like `#![cfg(unix)]`)
+        if !self.enabled(&file.attrs) {
+            return;
+        }
+        self.files.insert(key.clone(), BTreeSet::new());
+        let file_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut disabled = BTreeSet::new();
+        self.walk_items(&file.items, file_dir, mod_dir, false, &mut disabled);
+        self.files.insert(key, disabled);
+    }
+
+    /// walks the items of a file or of an inline module (`inline` is set)
+    fn walk_items(
+        &mut self,
+        items: &[Item],
+        file_dir: &Path,
+        mod_dir: &Path,
+        inline: bool,
+        disabled: &mut BTreeSet<usize>,
+    ) {
+        for item in items {
+            let attrs = item_attrs(item);
+            if !self.enabled(attrs) {
+                let span = item.span();
+                disabled.extend(span.start().line..=span.end().line);
+                continue;
+            }
+            let module = match item {
+                Item::Mod(module) => module,
+                Item::Macro(item_macro) => {
+                    self.walk_macro(item_macro, file_dir, mod_dir, inline, disabled);
+                    continue;
+                }
+                _ => continue,
+            };
+            let name = module.ident.unraw().to_string();
+            let path_attr = self.path_attr(attrs);
+            match (&module.content, path_attr) {
+                // inline modules are directories of the current module
+                (Some((_, items)), path_attr) => {
+                    let mod_dir = mod_dir.join(path_attr.unwrap_or(name));
+                    self.walk_items(items, file_dir, &mod_dir, true, disabled);
+                }
+                // `#[path]` is relative to the directory of the file (or of the current
+                // module in inline modules), and the file owns its directory like a `mod.rs`
+                (None, Some(path_attr)) => {
+                    let file = if inline { mod_dir } else { file_dir }.join(path_attr);
+                    let mod_dir = file.parent().unwrap_or_else(|| Path::new("")).to_path_buf();
+                    self.walk_file(&file, &mod_dir);
+                }
+                // `name.rs` or `name/mod.rs`
+                (None, None) => {
+                    let file = mod_dir.join(format!("{}.rs", name));
+                    let file = if file.exists() {
+                        file
+                    } else {
+                        mod_dir.join(&name).join("mod.rs")
+                    };
+                    self.walk_file(&file, &mod_dir.join(&name));
+                }
+            }
+        }
+    }
+
+    /// walks the items generated by a macro: the enabled branch of a `cfg_if!` invocation (the
+    /// lines of the other branches are disabled), or the expansion of a macro of the crate
+    /// invoked without arguments (its modules are relative to the invocation). Other macros
+    /// can't be expanded, so they are only checked for module declarations.
+    fn walk_macro(
+        &mut self,
+        item_macro: &syn::ItemMacro,
+        file_dir: &Path,
+        mod_dir: &Path,
+        inline: bool,
+        disabled: &mut BTreeSet<usize>,
+    ) {
+        let mac = &item_macro.mac;
+        if mac.path.is_ident("macro_rules") {
+            if let Some(name) = &item_macro.ident {
+                self.macros.insert(name.to_string(), mac.tokens.clone());
+            }
+            return;
+        }
+        let name = match mac.path.segments.last() {
+            Some(segment) => segment.ident.to_string(),
+            None => return,
+        };
+
+        // cfg_if! { if #[cfg(...)] { ... } else { ... } }
+        if name == "cfg_if" {
+            if let Ok(branches) = mac.parse_body_with(parse_cfg_if) {
+                let mut enabled = false;
+                for (predicate, span, tokens) in branches {
+                    if enabled || !predicate.is_none_or(|predicate| self.eval(&predicate)) {
+                        disabled.extend(span.start().line..=span.end().line);
+                        continue;
+                    }
+                    enabled = true;
+                    match syn::parse2::<syn::File>(tokens.clone()) {
+                        Ok(file) => {
+                            self.walk_items(&file.items, file_dir, mod_dir, inline, disabled)
+                        }
+                        Err(_) => self.macro_modules |= declares_modules(tokens),
+                    }
+                }
+                return;
+            }
+        }
+
+        // a macro of the crate
+        if let Some(definition) = self.macros.get(&name).cloned() {
+            if self.expanding.contains(&name) {
+                return;
+            }
+            let expansion = if mac.tokens.is_empty() {
+                syn::parse::Parser::parse2(parse_macro_rules, definition.clone())
+                    .ok()
+                    .and_then(|rules| rules.into_iter().find(|(matcher, _)| matcher.is_empty()))
+                    .and_then(|(_, body)| syn::parse2::<syn::File>(body).ok())
+            } else {
+                None
+            };
+            match expansion {
+                // (the lines of the expansion belong to the file of the definition)
+                Some(file) => {
+                    self.expanding.push(name);
+                    let mut expansion_disabled = BTreeSet::new();
+                    self.walk_items(
+                        &file.items,
+                        file_dir,
+                        mod_dir,
+                        inline,
+                        &mut expansion_disabled,
+                    );
+                    self.expanding.pop();
+                }
+                None => self.macro_modules |= declares_modules(definition),
+            }
+            return;
+        }
+
+        self.macro_modules |= declares_modules(mac.tokens.clone());
+    }
+
+    /// are the `#[cfg(...)]` attributes (including the ones of `#[cfg_attr(...)]`) satisfied?
+    fn enabled(&self, attrs: &[Attribute]) -> bool {
+        expand_attrs(attrs, &|predicate| self.eval(predicate))
+            .iter()
+            .filter(|meta| meta.path().is_ident("cfg"))
+            .all(|meta| match meta {
+                Meta::List(list) => list
+                    .parse_args::<Meta>()
+                    .map_or(true, |predicate| self.eval(&predicate)),
+                _ => true,
+            })
+    }
+
+    /// the value of a `#[path = "..."]` attribute (which can be set by `#[cfg_attr(...)]`)
+    fn path_attr(&self, attrs: &[Attribute]) -> Option<String> {
+        expand_attrs(attrs, &|predicate| self.eval(predicate))
+            .iter()
+            .filter(|meta| meta.path().is_ident("path"))
+            .find_map(|meta| match meta {
+                Meta::NameValue(name_value) => str_value(&name_value.value),
+                _ => None,
+            })
+    }
+
+    /// is a cfg option set (for the target, or by the build script)?
+    fn is_set(&self, option: (String, Option<String>)) -> bool {
+        self.cfg.0.contains(&option) || self.script_cfg.0.contains(&option)
+    }
+
+    /// evaluates a cfg predicate (`cfg(test)` is never set)
+    fn eval(&self, predicate: &Meta) -> bool {
+        let name = match predicate.path().get_ident() {
+            Some(ident) => ident.to_string(),
+            None => return false,
+        };
+        match predicate {
+            Meta::Path(_) if name == "test" => false,
+            Meta::Path(_) => self.is_set((name, None)),
+            Meta::NameValue(name_value) => match str_value(&name_value.value) {
+                Some(value) if name == "feature" => self.features.contains(&value),
+                Some(value) => self.is_set((name, Some(value))),
+                None => false,
+            },
+            Meta::List(list) => {
+                let predicates = list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .unwrap_or_default();
+                match name.as_str() {
+                    "all" => predicates.iter().all(|predicate| self.eval(predicate)),
+                    "any" => predicates.iter().any(|predicate| self.eval(predicate)),
+                    "not" => !predicates.iter().all(|predicate| self.eval(predicate)),
+                    _ => false,
+                }
+            }
+        }
+    }
+}
+
+/// the attributes, where each `#[cfg_attr(predicate, attrs...)]` is replaced by its
+/// attributes if the predicate holds
+fn expand_attrs(attrs: &[Attribute], eval: &dyn Fn(&Meta) -> bool) -> Vec<Meta> {
+    fn expand(meta: Meta, eval: &dyn Fn(&Meta) -> bool, expanded: &mut Vec<Meta>) {
+        let list = match &meta {
+            Meta::List(list) if meta.path().is_ident("cfg_attr") => list,
+            _ => return expanded.push(meta),
+        };
+        let mut metas = match list.parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+        {
+            Ok(metas) => metas.into_iter(),
+            Err(_) => return,
+        };
+        if metas.next().is_some_and(|predicate| eval(&predicate)) {
+            for meta in metas {
+                expand(meta, eval, expanded);
+            }
+        }
+    }
+    let mut expanded = Vec::new();
+    for attr in attrs {
+        expand(attr.meta.clone(), eval, &mut expanded);
+    }
+    expanded
+}
+
+/// parses the branches of a `cfg_if!` invocation:
+/// `if #[cfg(predicate)] { items } else if #[cfg(predicate)] { items } else { items }`
+fn parse_cfg_if(input: ParseStream) -> syn::Result<Vec<(Option<Meta>, Span, TokenStream)>> {
+    let mut branches = Vec::new();
+    loop {
+        let predicate = if input.peek(Token![if]) {
+            input.parse::<Token![if]>()?;
+            let attr = input.call(Attribute::parse_outer)?;
+            match attr.first().map(|attr| &attr.meta) {
+                Some(Meta::List(list)) if list.path.is_ident("cfg") => {
+                    Some(list.parse_args::<Meta>()?)
+                }
+                _ => return Err(input.error("expected #[cfg(...)]")),
+            }
+        } else {
+            None
+        };
+        let content;
+        let brace = braced!(content in input);
+        branches.push((predicate.clone(), brace.span.join(), content.parse()?));
+        if input.is_empty() || predicate.is_none() {
+            break;
+        }
+        input.parse::<Token![else]>()?;
+    }
+    Ok(branches)
+}
+
+/// parses the rules of a `macro_rules!` definition: `(matcher) => { body };` (with any delimiter)
+fn parse_macro_rules(input: ParseStream) -> syn::Result<Vec<(TokenStream, TokenStream)>> {
+    let mut rules = Vec::new();
+    while !input.is_empty() {
+        let matcher: Group = input.parse()?;
+        input.parse::<Token![=>]>()?;
+        let body: Group = input.parse()?;
+        rules.push((matcher.stream(), body.stream()));
+        if !input.is_empty() {
+            input.parse::<Token![;]>()?;
+        }
+    }
+    Ok(rules)
+}
+
+/// do these tokens contain module declarations (like `mod name;` or `mod $name;`)?
+fn declares_modules(tokens: TokenStream) -> bool {
+    let tokens: Vec<TokenTree> = tokens.into_iter().collect();
+    tokens.iter().enumerate().any(|(i, token)| match token {
+        TokenTree::Ident(ident) if ident == "mod" => {
+            let rest = match tokens.get(i + 1) {
+                Some(TokenTree::Punct(punct)) if punct.as_char() == '$' => &tokens[i + 2..],
+                _ => &tokens[i + 1..],
+            };
+            matches!(
+                rest,
+                [TokenTree::Ident(_), TokenTree::Punct(punct), ..] if punct.as_char() == ';'
+            )
+        }
+        TokenTree::Group(group) => declares_modules(group.stream()),
+        _ => false,
+    })
+}
+
+/// the attributes of an item
+fn item_attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Const(item) => &item.attrs,
+        Item::Enum(item) => &item.attrs,
+        Item::ExternCrate(item) => &item.attrs,
+        Item::Fn(item) => &item.attrs,
+        Item::ForeignMod(item) => &item.attrs,
+        Item::Impl(item) => &item.attrs,
+        Item::Macro(item) => &item.attrs,
+        Item::Mod(item) => &item.attrs,
+        Item::Static(item) => &item.attrs,
+        Item::Struct(item) => &item.attrs,
+        Item::Trait(item) => &item.attrs,
+        Item::TraitAlias(item) => &item.attrs,
+        Item::Type(item) => &item.attrs,
+        Item::Union(item) => &item.attrs,
+        Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+/// the value of a string literal
+fn str_value(expr: &syn::Expr) -> Option<String> {
+    match expr {
+        syn::Expr::Lit(syn::ExprLit {
+            lit: syn::Lit::Str(lit),
+            ..
+        }) => Some(lit.value()),
+        _ => None,
+    }
+}
+
+/// removes the `.` and `..` of a path (like rustc, without following symlinks)
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => (),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// a cfg with the given options
+    fn cfg(options: &[&str]) -> Cfg {
+        Cfg::parse(options.iter().copied())
+    }
+
+    /// writes the files of a crate in a temporary folder
+    fn crate_dir(files: &[(&str, &str)]) -> TempDir {
+        let dir = TempDir::new("dephell_modules").unwrap();
+        for (path, content) in files {
+            let path = dir.path().join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, content).unwrap();
+        }
+        dir
+    }
+
+    /// walks the module tree of `src/lib.rs` for the given targets
+    fn walk(dir: &TempDir, cfgs: &[Cfg]) -> BTreeMap<String, BTreeSet<usize>> {
+        let root = CrateRoot {
+            path: dir.path().join("src/lib.rs"),
+            target: true,
+            host: false,
+        };
+        let module_files = get_module_files(&[root], cfgs, &cfg(&[]), &cfg(&[]), &BTreeSet::new());
+        module_files
+            .files
+            .into_iter()
+            .map(|(file, disabled)| {
+                let file = Path::new(&file).strip_prefix(dir.path()).unwrap();
+                (file.to_string_lossy().into_owned(), disabled)
+            })
+            .collect()
+    }
+
+    fn paths(files: &BTreeMap<String, BTreeSet<usize>>) -> Vec<&str> {
+        files.keys().map(|file| file.as_str()).collect()
+    }
+
+    #[test]
+    fn path_attribute_in_inline_module() {
+        let dir = crate_dir(&[
+            (
+                "src/lib.rs",
+                "mod outer {\n    #[path = \"renamed.rs\"]\n    mod inner;\n}\n",
+            ),
+            ("src/outer/renamed.rs", "mod nested;\n"),
+            ("src/outer/nested.rs", ""),
+            ("src/renamed.rs", ""),
+        ]);
+        let files = walk(&dir, &[cfg(&["unix"])]);
+        // the path is relative to the directory of the inline module, and the file owns
+        // its directory like a `mod.rs`
+        assert_eq!(
+            paths(&files),
+            ["src/lib.rs", "src/outer/nested.rs", "src/outer/renamed.rs"]
+        );
+    }
+
+    #[test]
+    fn path_set_by_cfg_attr() {
+        let dir = crate_dir(&[
+            (
+                "src/lib.rs",
+                "#[cfg_attr(unix, path = \"unix.rs\")]\n#[cfg_attr(windows, path = \"windows.rs\")]\nmod imp;\n",
+            ),
+            ("src/unix.rs", ""),
+            ("src/windows.rs", ""),
+            ("src/imp.rs", ""),
+        ]);
+        let files = walk(&dir, &[cfg(&["unix"])]);
+        assert_eq!(paths(&files), ["src/lib.rs", "src/unix.rs"]);
+        let files = walk(&dir, &[cfg(&["unix"]), cfg(&["windows"])]);
+        assert_eq!(
+            paths(&files),
+            ["src/lib.rs", "src/unix.rs", "src/windows.rs"]
+        );
+        let files = walk(&dir, &[cfg(&[])]);
+        assert_eq!(paths(&files), ["src/imp.rs", "src/lib.rs"]);
+    }
+
+    #[test]
+    fn file_disabled_by_inner_cfg() {
+        let dir = crate_dir(&[
+            ("src/lib.rs", "mod linux;\nmod other;\n"),
+            ("src/linux.rs", "#![cfg(target_os = \"linux\")]\nmod sub;\n"),
+            ("src/linux/sub.rs", ""),
+            ("src/other.rs", "#![cfg(any(unix, windows))]\n"),
+        ]);
+        let files = walk(&dir, &[cfg(&["windows", "target_os=windows"])]);
+        assert_eq!(paths(&files), ["src/lib.rs", "src/other.rs"]);
+        let files = walk(&dir, &[cfg(&["unix", "target_os=linux"])]);
+        assert_eq!(
+            paths(&files),
+            [
+                "src/lib.rs",
+                "src/linux.rs",
+                "src/linux/sub.rs",
+                "src/other.rs"
+            ]
+        );
+    }
+
+    #[test]
+    fn lines_disabled_on_every_target() {
+        let dir = crate_dir(&[(
+            "src/lib.rs",
+            "#[cfg(unix)]\nfn unix() {}\n#[cfg(windows)]\nfn windows() {}\n#[cfg(test)]\nmod tests {\n}\nfn always() {}\n",
+        )]);
+        let files = walk(&dir, &[cfg(&["unix"])]);
+        assert_eq!(
+            files["src/lib.rs"],
+            [3, 4, 5, 6, 7].iter().copied().collect()
+        );
+        let files = walk(&dir, &[cfg(&["unix"]), cfg(&["windows"])]);
+        assert_eq!(files["src/lib.rs"], [5, 6, 7].iter().copied().collect());
+    }
+
+    #[test]
+    fn host_roots_use_the_host_cfg() {
+        let dir = crate_dir(&[
+            (
+                "build.rs",
+                "#[cfg(unix)]\nmod unix;\n#[cfg(windows)]\nmod windows;\n",
+            ),
+            ("unix.rs", ""),
+            ("windows.rs", ""),
+        ]);
+        let root = CrateRoot {
+            path: dir.path().join("build.rs"),
+            target: false,
+            host: true,
+        };
+        let module_files = get_module_files(
+            &[root],
+            &[cfg(&["windows"])],
+            &cfg(&["unix"]),
+            &cfg(&[]),
+            &BTreeSet::new(),
+        );
+        let unix = dir.path().join("unix.rs").to_string_lossy().into_owned();
+        let windows = dir.path().join("windows.rs").to_string_lossy().into_owned();
+        assert!(module_files.files.contains_key(&unix));
+        assert!(!module_files.files.contains_key(&windows));
+    }
+
+    #[test]
+    fn cfg_if_branches() {
+        let dir = crate_dir(&[
+            (
+                "src/lib.rs",
+                "cfg_if::cfg_if! {\n    if #[cfg(windows)] {\n        mod windows;\n    } else if #[cfg(unix)] {\n        mod unix;\n    } else {\n        mod other;\n    }\n}\n",
+            ),
+            ("src/windows.rs", ""),
+            ("src/unix.rs", ""),
+            ("src/other.rs", ""),
+        ]);
+        let files = walk(&dir, &[cfg(&["unix"])]);
+        assert_eq!(paths(&files), ["src/lib.rs", "src/unix.rs"]);
+        // the lines of the other branches are disabled
+        assert_eq!(
+            files["src/lib.rs"],
+            [2, 3, 4, 6, 7, 8].iter().copied().collect()
+        );
+        let files = walk(&dir, &[cfg(&[])]);
+        assert_eq!(paths(&files), ["src/lib.rs", "src/other.rs"]);
+    }
+
+    #[test]
+    fn modules_declared_by_macros() {
+        // a macro of the crate invoked without arguments is expanded where it is invoked
+        let dir = crate_dir(&[
+            ("src/lib.rs", "#[macro_use]\nmod macros;\ncrate_root!();\n"),
+            (
+                "src/macros.rs",
+                "macro_rules! crate_root {\n    () => {\n        mod de;\n        #[cfg(test)]\n        mod tests;\n    };\n}\n",
+            ),
+            ("src/de.rs", ""),
+            ("src/tests.rs", ""),
+        ]);
+        let files = walk(&dir, &[cfg(&[])]);
+        assert_eq!(paths(&files), ["src/de.rs", "src/lib.rs", "src/macros.rs"]);
+
+        // other macros declaring modules can't be expanded: every file of the folder is taken
+        let dir = crate_dir(&[
+            ("src/lib.rs", "other_crate::wrap! {\n    mod de;\n}\n"),
+            ("src/de.rs", ""),
+            ("src/de/inner.rs", ""),
+        ]);
+        let files = walk(&dir, &[cfg(&[])]);
+        assert_eq!(
+            paths(&files),
+            ["src/de.rs", "src/de/inner.rs", "src/lib.rs"]
+        );
+        let dir = crate_dir(&[
+            (
+                "src/lib.rs",
+                "macro_rules! modules {\n    ($($name:ident),*) => { $(mod $name;)* };\n}\nmodules!(de);\n",
+            ),
+            ("src/de.rs", ""),
+            ("src/de/inner.rs", ""),
+        ]);
+        let files = walk(&dir, &[cfg(&[])]);
+        assert_eq!(
+            paths(&files),
+            ["src/de.rs", "src/de/inner.rs", "src/lib.rs"]
+        );
+    }
+
+    #[test]
+    fn cfgs_set_by_the_build_script() {
+        let dir = crate_dir(&[
+            (
+                "src/lib.rs",
+                "#[cfg(wrap_proc_macro)]\nmod wrapper;\n#[cfg(not(wrap_proc_macro))]\nmod fallback;\n#[cfg(span = \"locations\")]\nmod locations;\n",
+            ),
+            ("src/wrapper.rs", ""),
+            ("src/fallback.rs", ""),
+            ("src/locations.rs", ""),
+        ]);
+        let root = CrateRoot {
+            path: dir.path().join("src/lib.rs"),
+            target: true,
+            host: false,
+        };
+        let script_cfg = Cfg::parse(vec!["wrap_proc_macro", "span=\"locations\""]);
+        let module_files = get_module_files(
+            &[root],
+            &[cfg(&["unix"])],
+            &cfg(&["unix"]),
+            &script_cfg,
+            &BTreeSet::new(),
+        );
+        let mut files: Vec<_> = module_files
+            .paths()
+            .into_iter()
+            .map(|file| {
+                let file = Path::new(&file).strip_prefix(dir.path()).unwrap();
+                file.to_string_lossy().into_owned()
+            })
+            .collect();
+        files.sort();
+        assert_eq!(files, ["src/lib.rs", "src/locations.rs", "src/wrapper.rs"]);
+    }
+}
@@ -0,0 +1,409 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::advisory::AdvisoryKind;
+use crate::config::PolicyConfig;
+use crate::AnalysisReport;
+
+/// Violation is a rule of the policy that a dependency does not respect.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Violation {
+    /// the rule that is not respected (as named in the configuration)
+    pub rule: String,
+    /// the package (see `package_key`), or the name of the dependency for duplicate versions
+    pub package: String,
+    /// a human-readable explanation
+    pub message: String,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} ({})", self.package, self.message, self.rule)
+    }
+}
+
+/// PolicyResult is the outcome of checking a report against a policy.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct PolicyResult {
+    /// the violations, ordered by rule and package
+    pub violations: Vec<Violation>,
+    /// the rules that could not be evaluated (because a metric was not collected), and why
+    pub not_evaluated: BTreeMap<String, String>,
+}
+
+/// Checks the third-party dependencies of a report against the thresholds of a policy.
+/// Workspace crates are never checked.
+pub fn check_policy(report: &AnalysisReport, policy: &PolicyConfig) -> PolicyResult {
+    let mut result = PolicyResult::default();
+    let mut violation = |rule: &str, package: &str, message: String| {
+        result.violations.push(Violation {
+            rule: rule.to_string(),
+            package: package.to_string(),
+            message,
+        })
+    };
+    let third_party = || {
+        report
+            .analysis_result
+            .iter()
+            .filter(|(_, package_risk)| !package_risk.internal)
+    };
+
+    // max-total-unsafe-loc
+    if let Some(max) = policy.max_total_unsafe_loc {
+        for (package, package_risk) in third_party() {
+            if package_risk.total_unsafe_loc > max {
+                violation(
+                    "max-total-unsafe-loc",
+                    package,
+                    format!(
+                        "{} lines of unsafe code (including its dependencies), more than {}",
+                        package_risk.total_unsafe_loc, max
+                    ),
+                );
+            }
+        }
+    }
+
+    // max-transitive-deps (only for direct dependencies)
+    if let Some(max) = policy.max_transitive_deps {
+        for (package, package_risk) in third_party() {
+            let transitive_deps = package_risk.transitive_dependencies.len();
+            if report.main_dependencies.contains(package) && transitive_deps > max {
+                violation(
+                    "max-transitive-deps",
+                    package,
+                    format!(
+                        "pulls {} transitive dependencies, more than {}",
+                        transitive_deps, max
+                    ),
+                );
+            }
+        }
+    }
+
+    // min-crates-io-dependent
+    if let Some(min) = policy.min_crates_io_dependent {
+        for (package, package_risk) in third_party() {
+            match package_risk.crates_io_dependent {
+                Some(dependent) if dependent < min => violation(
+                    "min-crates-io-dependent",
+                    package,
+                    format!(
+                        "only {} crates on crates.io depend on it, less than {}",
+                        dependent, min
+                    ),
+                ),
+                _ => (),
+            }
+        }
+    }
+
+    // max-days-since-update
+    if let Some(max) = policy.max_days_since_update {
+        let today = report.generated_at.date().naive_utc();
+        for (package, package_risk) in third_party() {
+            if let Some(last_updated) = package_risk.crates_io_last_updated {
+                let days = (today - last_updated).num_days();
+                if days > max {
+                    violation(
+                        "max-days-since-update",
+                        package,
+                        format!(
+                            "last updated on crates.io {} days ago ({}), more than {}",
+                            days, last_updated, max
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    // forbid-duplicate-versions
+    if policy.forbid_duplicate_versions.unwrap_or(false) {
+        for (name, versions) in &report.duplicate_versions {
+            let versions: Vec<&str> = versions
+                .iter()
+                .map(|version| version.version.as_str())
+                .collect();
+            violation(
+                "forbid-duplicate-versions",
+                name,
+                format!(
+                    "imported with {} versions ({})",
+                    versions.len(),
+                    versions.join(", ")
+                ),
+            );
+        }
+    }
+
+    // forbid-advisories + forbid-unmaintained
+    let advisory_rules = [
+        (
+            "forbid-advisories",
+            policy.forbid_advisories,
+            &[AdvisoryKind::Vulnerability, AdvisoryKind::Unsound][..],
+        ),
+        (
+            "forbid-unmaintained",
+            policy.forbid_unmaintained,
+            &[AdvisoryKind::Unmaintained][..],
+        ),
+    ];
+    for (rule, enabled, kinds) in advisory_rules.iter() {
+        if !enabled.unwrap_or(false) {
+            continue;
+        }
+        for (package, package_risk) in third_party() {
+            let advisories = package_risk.advisories.iter().flatten();
+            for advisory in advisories.filter(|advisory| kinds.contains(&advisory.kind)) {
+                let patched = if advisory.patched_versions.is_empty() {
+                    "no patched version".to_string()
+                } else {
+                    format!("patched in {}", advisory.patched_versions.join(", "))
+                };
+                violation(
+                    rule,
+                    package,
+                    format!("{} {} ({})", advisory.id, advisory.title, patched),
+                );
+            }
+        }
+    }
+
+    // forbid-yanked
+    if policy.forbid_yanked.unwrap_or(false) {
+        for (package, package_risk) in third_party() {
+            if package_risk.crates_io_yanked == Some(true) {
+                violation(
+                    "forbid-yanked",
+                    package,
+                    "this version is yanked from crates.io".to_string(),
+                );
+            }
+        }
+    }
+
+    // rules relying on metrics that were not collected
+    let rules = [
+        (
+            "min-crates-io-dependent",
+            policy.min_crates_io_dependent.is_some(),
+            "crates_io_dependent",
+        ),
+        (
+            "max-days-since-update",
+            policy.max_days_since_update.is_some(),
+            "crates_io_last_updated",
+        ),
+        (
+            "forbid-advisories",
+            policy.forbid_advisories.unwrap_or(false),
+            "advisories",
+        ),
+        (
+            "forbid-unmaintained",
+            policy.forbid_unmaintained.unwrap_or(false),
+            "advisories",
+        ),
+        (
+            "forbid-yanked",
+            policy.forbid_yanked.unwrap_or(false),
+            "crates_io_yanked",
+        ),
+    ];
+    for (rule, enabled, metric) in rules.iter() {
+        if let (true, Some(reason)) = (enabled, report.unavailable_metrics.get(*metric)) {
+            result.not_evaluated.insert(
+                rule.to_string(),
+                format!("{} was not collected ({})", metric, reason),
+            );
+        }
+    }
+
+    result
+        .violations
+        .sort_by(|a, b| (&a.rule, &a.package).cmp(&(&b.rule, &b.package)));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::advisory::Advisory;
+    use crate::{AnalysisOptions, PackageRisk};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use std::collections::BTreeSet;
+
+    /// a report generated on 2021-03-01 with the given packages
+    fn report(packages: Vec<PackageRisk>) -> AnalysisReport {
+        AnalysisReport {
+            schema_version: crate::analysis::SCHEMA_VERSION,
+            tool_version: "test".to_string(),
+            generated_at: Utc.ymd(2021, 3, 1).and_hms(0, 0, 0),
+            manifest_path: "Cargo.toml".into(),
+            options: AnalysisOptions::new("Cargo.toml"),
+            root_crates: BTreeSet::new(),
+            main_dependencies: BTreeSet::new(),
+            dependency_edges: Vec::new(),
+            analysis_result: packages
+                .into_iter()
+                .map(|package| (format!("{} {}", package.name, package.version), package))
+                .collect(),
+            duplicate_versions: BTreeMap::new(),
+            unavailable_metrics: BTreeMap::new(),
+        }
+    }
+
+    fn package(name: &str, version: &str) -> PackageRisk {
+        PackageRisk {
+            name: name.to_string(),
+            version: version.to_string(),
+            ..PackageRisk::default()
+        }
+    }
+
+    fn advisory(id: &str, kind: AdvisoryKind) -> Advisory {
+        Advisory {
+            id: id.to_string(),
+            title: "title".to_string(),
+            date: "2021-01-01".to_string(),
+            kind,
+            aliases: Vec::new(),
+            url: String::new(),
+            patched_versions: Vec::new(),
+        }
+    }
+
+    /// the (rule, package) of the violations
+    fn violations(result: &PolicyResult) -> Vec<(&str, &str)> {
+        result
+            .violations
+            .iter()
+            .map(|violation| (violation.rule.as_str(), violation.package.as_str()))
+            .collect()
+    }
+
+    #[test]
+    fn thresholds() {
+        let mut unsafe_dep = package("unsafe_dep", "1.0.0");
+        unsafe_dep.total_unsafe_loc = 11;
+        unsafe_dep.transitive_dependencies = ["a 1.0.0", "b 1.0.0", "c 1.0.0"]
+            .iter()
+            .map(|dep| dep.to_string())
+            .collect();
+        unsafe_dep.crates_io_dependent = Some(5);
+        unsafe_dep.crates_io_last_updated = Some(NaiveDate::from_ymd(2020, 1, 1));
+        // exactly at the thresholds
+        let mut fine_dep = package("fine_dep", "1.0.0");
+        fine_dep.total_unsafe_loc = 10;
+        fine_dep.crates_io_dependent = Some(10);
+        fine_dep.crates_io_last_updated = Some(NaiveDate::from_ymd(2020, 3, 1));
+        // workspace crates are never checked
+        let mut internal = package("internal", "0.1.0");
+        internal.internal = true;
+        internal.total_unsafe_loc = 100;
+        internal.crates_io_dependent = Some(0);
+
+        let mut report = report(vec![unsafe_dep, fine_dep, internal]);
+        report.main_dependencies = ["unsafe_dep 1.0.0", "internal 0.1.0"]
+            .iter()
+            .map(|dep| dep.to_string())
+            .collect();
+        let policy = PolicyConfig {
+            max_total_unsafe_loc: Some(10),
+            max_transitive_deps: Some(2),
+            min_crates_io_dependent: Some(10),
+            max_days_since_update: Some(365),
+            ..PolicyConfig::default()
+        };
+        let result = check_policy(&report, &policy);
+        assert_eq!(
+            violations(&result),
+            vec![
+                ("max-days-since-update", "unsafe_dep 1.0.0"),
+                ("max-total-unsafe-loc", "unsafe_dep 1.0.0"),
+                ("max-transitive-deps", "unsafe_dep 1.0.0"),
+                ("min-crates-io-dependent", "unsafe_dep 1.0.0"),
+            ]
+        );
+        assert!(result.not_evaluated.is_empty());
+    }
+
+    #[test]
+    fn forbidden_packages() {
+        let mut vulnerable = package("vulnerable", "1.0.0");
+        vulnerable.advisories = Some(vec![
+            advisory("RUSTSEC-2021-0001", AdvisoryKind::Vulnerability),
+            advisory("RUSTSEC-2021-0002", AdvisoryKind::Notice),
+        ]);
+        let mut unmaintained = package("unmaintained", "1.0.0");
+        unmaintained.advisories = Some(vec![advisory(
+            "RUSTSEC-2021-0003",
+            AdvisoryKind::Unmaintained,
+        )]);
+        let mut yanked = package("yanked", "1.0.0");
+        yanked.crates_io_yanked = Some(true);
+
+        let mut report = report(vec![vulnerable, unmaintained, yanked]);
+        report
+            .duplicate_versions
+            .insert("dup".to_string(), Vec::new());
+        let policy = PolicyConfig {
+            forbid_duplicate_versions: Some(true),
+            forbid_advisories: Some(true),
+            forbid_unmaintained: Some(true),
+            forbid_yanked: Some(true),
+            ..PolicyConfig::default()
+        };
+        let result = check_policy(&report, &policy);
+        assert_eq!(
+            violations(&result),
+            vec![
+                ("forbid-advisories", "vulnerable 1.0.0"),
+                ("forbid-duplicate-versions", "dup"),
+                ("forbid-unmaintained", "unmaintained 1.0.0"),
+                ("forbid-yanked", "yanked 1.0.0"),
+            ]
+        );
+        assert!(result.violations[0]
+            .message
+            .starts_with("RUSTSEC-2021-0001 title (no patched version)"));
+
+        // disabled rules are not checked
+        let policy = PolicyConfig {
+            forbid_yanked: Some(false),
+            ..PolicyConfig::default()
+        };
+        assert!(check_policy(&report, &policy).violations.is_empty());
+    }
+
+    #[test]
+    fn rules_without_metrics_are_not_evaluated() {
+        let mut report = report(vec![package("dep", "1.0.0")]);
+        report
+            .unavailable_metrics
+            .insert("advisories".to_string(), "no advisory db".to_string());
+        report
+            .unavailable_metrics
+            .insert("crates_io_yanked".to_string(), "offline".to_string());
+        let policy = PolicyConfig {
+            forbid_advisories: Some(true),
+            forbid_unmaintained: Some(false),
+            forbid_yanked: Some(true),
+            ..PolicyConfig::default()
+        };
+        let result = check_policy(&report, &policy);
+        assert!(result.violations.is_empty());
+        assert_eq!(
+            result.not_evaluated.keys().collect::<Vec<_>>(),
+            vec!["forbid-advisories", "forbid-yanked"]
+        );
+        assert_eq!(
+            result.not_evaluated["forbid-yanked"],
+            "crates_io_yanked was not collected (offline)"
+        );
+    }
+}
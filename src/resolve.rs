@@ -0,0 +1,612 @@
+use guppy::graph::cargo::{CargoOptions, CargoResolverVersion};
+use guppy::graph::feature::{
+    FeatureFilter, FeatureFilterFn, FeatureId, FeatureSet, StandardFeatures,
+};
+use guppy::graph::{DependencyDirection, PackageGraph, PackageLink, PackageMetadata, PackageSet};
+use guppy::{PackageId, Platform, TargetFeatures};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+
+use crate::Error;
+
+//
+// Feature Selection
+// =================
+//
+
+/// FeatureSelection mirrors cargo's `--features`, `--all-features` and `--no-default-features`.
+#[derive(Default, Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct FeatureSelection {
+    /// features to activate, either `feature`, `package/feature` (for a root crate)
+    /// or `dependency/feature` (for a dependency of a root crate)
+    pub features: Vec<String>,
+    /// activate all the features of the root crates
+    pub all_features: bool,
+    /// do not activate the `default` feature of the root crates
+    pub no_default_features: bool,
+}
+
+impl FeatureSelection {
+    /// parses a list of features the way cargo does (features can be separated by commas or spaces)
+    pub fn parse_features<'a>(features: impl Iterator<Item = &'a str>) -> Vec<String> {
+        features
+            .flat_map(|features| features.split(|c: char| c == ',' || c.is_whitespace()))
+            .filter(|feature| !feature.is_empty())
+            .map(|feature| feature.to_string())
+            .collect()
+    }
+
+    /// the arguments to pass to cargo to obtain the same feature selection
+    pub fn cargo_args(&self) -> Vec<String> {
+        let mut args = Vec::new();
+        if !self.features.is_empty() {
+            args.push("--features".to_string());
+            args.push(self.features.join(","));
+        }
+        if self.all_features {
+            args.push("--all-features".to_string());
+        }
+        if self.no_default_features {
+            args.push("--no-default-features".to_string());
+        }
+        args
+    }
+
+    fn standard_features(&self) -> StandardFeatures {
+        if self.all_features {
+            StandardFeatures::All
+        } else if self.no_default_features {
+            StandardFeatures::None
+        } else {
+            StandardFeatures::Default
+        }
+    }
+}
+
+//
+// Dependency Kinds
+// ================
+//
+
+/// DependencyKind is the way a package ends up being imported by the root crates.
+#[derive(
+    Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize, JsonSchema,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum DependencyKind {
+    /// compiled in the root crates
+    Normal,
+    /// compiled for the host and executed at compile time
+    /// (build dependencies, procedural macros and their own dependencies)
+    Build,
+    /// only used to test the root crates (dev dependencies and their own dependencies)
+    Dev,
+}
+
+impl DependencyKind {
+    /// the kinds of a link: one per section declaring it, as a dependency can be both a normal
+    /// and a build dependency (proc macros are executed at compile time, like build dependencies)
+    pub(crate) fn of_link(dep_link: &PackageLink) -> BTreeSet<Self> {
+        let mut kinds = BTreeSet::new();
+        if dep_link.to().is_proc_macro() {
+            kinds.insert(DependencyKind::Build);
+            return kinds;
+        }
+        if dep_link.normal().is_present() {
+            kinds.insert(DependencyKind::Normal);
+        }
+        if dep_link.build().is_present() {
+            kinds.insert(DependencyKind::Build);
+        }
+        if kinds.is_empty() {
+            kinds.insert(DependencyKind::Dev);
+        }
+        kinds
+    }
+
+    /// the kind of a package imported by a package of kind `self` via a link of kind `link_kind`
+    fn then(self, link_kind: Self) -> Self {
+        match self {
+            DependencyKind::Normal => link_kind,
+            kind => kind,
+        }
+    }
+}
+
+//
+// Resolved Graph
+// ==============
+//
+
+/// ResolvedGraph is the part of the package graph that actually gets built
+/// for a set of root crates, a feature selection and a set of target platforms
+/// (according to guppy's simulation of cargo).
+pub struct ResolvedGraph<'g> {
+    package_graph: &'g PackageGraph,
+    root_crates: HashSet<PackageId>,
+    features: FeatureSelection,
+    targets: Vec<String>,
+    include_dev: bool,
+    /// the packages that get built (including the root crates)
+    packages: PackageSet<'g>,
+    /// the links that are followed, indexed by the package they come from
+    links: HashMap<&'g PackageId, Vec<PackageLink<'g>>>,
+    /// the features enabled on each package (on any of the platforms)
+    package_features: HashMap<&'g PackageId, BTreeSet<&'g str>>,
+}
+
+impl<'g> ResolvedGraph<'g> {
+    /// resolves the dependencies of the `root_crates` with the given feature selection,
+    /// for the given target triples (or for any platform if no target triple is given).
+    /// The dev dependencies of the root crates are included if `include_dev` is set.
+    pub fn new(
+        package_graph: &'g PackageGraph,
+        root_crates: &HashSet<PackageId>,
+        features: &FeatureSelection,
+        targets: &[String],
+        include_dev: bool,
+    ) -> Result<Self, Error> {
+        Self::resolve(
+            package_graph,
+            root_crates,
+            features,
+            targets,
+            include_dev,
+            None,
+        )
+    }
+
+    /// resolves the same graph, as if `omitted` was not a dependency of anyone
+    pub fn without(&self, omitted: &PackageId) -> Result<Self, Error> {
+        Self::resolve(
+            self.package_graph,
+            &self.root_crates,
+            &self.features,
+            &self.targets,
+            self.include_dev,
+            Some(omitted),
+        )
+    }
+
+    fn resolve(
+        package_graph: &'g PackageGraph,
+        root_crates: &HashSet<PackageId>,
+        features: &FeatureSelection,
+        targets: &[String],
+        include_dev: bool,
+        omitted: Option<&PackageId>,
+    ) -> Result<Self, Error> {
+        // the features enabled on the root crates
+        let initials = package_graph.resolve_ids(root_crates.iter())?;
+        let root_features =
+            initials.to_feature_set(feature_filter(package_graph, root_crates, features));
+        let initials = root_features.union(&dependency_features(
+            package_graph,
+            root_crates,
+            &root_features,
+            features,
+        )?);
+
+        // the platforms to build for (`None` means any platform)
+        let target_platforms: Vec<Option<Platform>> = if targets.is_empty() {
+            vec![None]
+        } else {
+            targets
+                .iter()
+                .map(|target| {
+                    Platform::new(target, TargetFeatures::Unknown)
+                        .map(Some)
+                        .map_err(|err| Error::UnknownTarget(target.to_string(), err))
+                })
+                .collect::<Result<_, _>>()?
+        };
+        let host_platform = if targets.is_empty() {
+            None
+        } else {
+            Platform::current()
+        };
+
+        // the feature resolver used by cargo
+        let resolver_version = resolver_version(package_graph);
+
+        // simulate a cargo build for each platform
+        let mut packages = package_graph.resolve_none();
+        let mut links: HashMap<&'g PackageId, Vec<PackageLink<'g>>> = HashMap::new();
+        let mut package_features: HashMap<&'g PackageId, BTreeSet<&'g str>> = HashMap::new();
+        let mut seen = HashSet::new();
+        for target_platform in target_platforms {
+            let mut opts = CargoOptions::new();
+            opts.set_version(resolver_version);
+            opts.set_include_dev(include_dev);
+            opts.set_target_platform(target_platform);
+            opts.set_host_platform(host_platform.clone());
+            if let Some(omitted) = omitted {
+                opts.add_omitted_packages(std::iter::once(omitted));
+            }
+            let cargo_set = initials.clone().into_cargo_set(&opts)?;
+
+            // obtain the packages and links that are part of the build (for the target and the host)
+            let target_features = cargo_set.target_features();
+            let host_features = cargo_set.host_features();
+            let features = target_features.union(host_features);
+            packages = packages.union(&features.to_package_set());
+            for feature_list in features.packages_with_features(DependencyDirection::Forward) {
+                package_features
+                    .entry(feature_list.package().id())
+                    .or_default()
+                    .extend(feature_list.features());
+            }
+            // (links from the target to the host, like build dependencies and proc macros,
+            // are not part of either feature set)
+            let dep_links = activated_links(target_features)
+                .chain(activated_links(host_features))
+                .chain(cargo_set.build_dep_links())
+                .chain(cargo_set.proc_macro_links())
+                // dev dependencies are only followed from the root crates
+                .filter(|dep_link| {
+                    !dep_link.dev_only()
+                        || (include_dev && root_crates.contains(dep_link.from().id()))
+                });
+            for dep_link in dep_links {
+                if seen.insert((dep_link.from().id(), dep_link.to().id())) {
+                    links
+                        .entry(dep_link.from().id())
+                        .or_default()
+                        .push(dep_link);
+                }
+            }
+        }
+
+        //
+        Ok(Self {
+            package_graph,
+            root_crates: root_crates.clone(),
+            features: features.clone(),
+            targets: targets.to_vec(),
+            include_dev,
+            packages,
+            links,
+            package_features,
+        })
+    }
+
+    /// the metadata of a package of the graph
+    pub fn metadata(&self, package_id: &PackageId) -> PackageMetadata<'g> {
+        self.package_graph.metadata(package_id).unwrap()
+    }
+
+    /// is this package part of the build?
+    pub fn contains(&self, package_id: &PackageId) -> bool {
+        self.packages.contains(package_id).unwrap_or(false)
+    }
+
+    /// iterates over the packages part of the build, in topological order
+    pub fn package_ids(
+        &self,
+        direction: DependencyDirection,
+    ) -> impl Iterator<Item = &'g PackageId> + '_ {
+        self.packages.package_ids(direction)
+    }
+
+    /// the features enabled on this package (on any of the platforms)
+    pub fn enabled_features(&self, package_id: &PackageId) -> BTreeSet<String> {
+        self.package_features
+            .get(package_id)
+            .into_iter()
+            .flatten()
+            .map(|feature| feature.to_string())
+            .collect()
+    }
+
+    /// the links followed from this package
+    pub fn direct_links(&self, package_id: &PackageId) -> impl Iterator<Item = &PackageLink<'g>> {
+        self.links.get(package_id).into_iter().flatten()
+    }
+
+    /// all the packages that end up being imported by this package (not including itself)
+    pub fn transitive_dependencies(&self, package_id: &PackageId) -> HashSet<&'g PackageId> {
+        let mut transitive_dependencies = HashSet::new();
+        let mut to_visit = VecDeque::new();
+        to_visit.push_back(package_id);
+        while let Some(package_id) = to_visit.pop_front() {
+            for dep_link in self.direct_links(package_id) {
+                let dep_id = dep_link.to().id();
+                if transitive_dependencies.insert(dep_id) {
+                    to_visit.push_back(dep_id);
+                }
+            }
+        }
+        transitive_dependencies.remove(package_id);
+        transitive_dependencies
+    }
+
+    /// the kinds of dependency through which each package is imported by the root crates
+    pub fn dependency_kinds(&self) -> HashMap<&'g PackageId, BTreeSet<DependencyKind>> {
+        let mut dependency_kinds: HashMap<&'g PackageId, BTreeSet<DependencyKind>> = HashMap::new();
+        let mut to_visit = VecDeque::new();
+        for root_crate in self.root_crates.iter() {
+            let root_crate = self.metadata(root_crate).id();
+            dependency_kinds
+                .entry(root_crate)
+                .or_default()
+                .insert(DependencyKind::Normal);
+            to_visit.push_back((root_crate, DependencyKind::Normal));
+        }
+        // propagate kinds until nothing changes
+        while let Some((package_id, kind)) = to_visit.pop_front() {
+            for dep_link in self.direct_links(package_id) {
+                let dep_id = dep_link.to().id();
+                for link_kind in DependencyKind::of_link(dep_link) {
+                    let dep_kind = kind.then(link_kind);
+                    if dependency_kinds.entry(dep_id).or_default().insert(dep_kind) {
+                        to_visit.push_back((dep_id, dep_kind));
+                    }
+                }
+            }
+        }
+        dependency_kinds
+    }
+}
+
+//
+// Helpers
+// =======
+//
+
+/// the names of the root crates
+fn root_crate_names<'g>(
+    package_graph: &'g PackageGraph,
+    root_crates: &HashSet<PackageId>,
+) -> HashSet<&'g str> {
+    root_crates
+        .iter()
+        .filter_map(|root_crate| package_graph.metadata(root_crate).ok())
+        .map(|package| package.name())
+        .collect()
+}
+
+/// creates a guppy feature filter out of a feature selection,
+/// for the features of the root crates
+fn feature_filter<'g: 'a, 'a>(
+    package_graph: &'g PackageGraph,
+    root_crates: &HashSet<PackageId>,
+    features: &'a FeatureSelection,
+) -> impl FeatureFilter<'g> + 'a {
+    let root_crate_names = root_crate_names(package_graph, root_crates);
+    let mut base = features.standard_features();
+    FeatureFilterFn::new(move |feature_graph, feature_id| {
+        if base.accept(feature_graph, feature_id) {
+            return true;
+        }
+        let feature = match feature_id.feature() {
+            Some(feature) => feature,
+            // the base feature has already been selected by the base filter
+            None => return false,
+        };
+        let package_name = feature_graph
+            .package_graph()
+            .metadata(feature_id.package_id())
+            .map(|package| package.name())
+            .unwrap_or_default();
+        features
+            .features
+            .iter()
+            .any(|selected| match selected.split_once('/') {
+                Some((package, selected)) => {
+                    root_crate_names.contains(package)
+                        && package == package_name
+                        && selected == feature
+                }
+                None => selected == feature,
+            })
+    })
+}
+
+/// the features enabled by the `dependency/feature` (or `dependency?/feature`) selections,
+/// like cargo this also enables the dependency itself if it is optional, unless `?` is used
+/// (then the feature is only enabled if the root features already enable the dependency)
+fn dependency_features<'g>(
+    package_graph: &'g PackageGraph,
+    root_crates: &HashSet<PackageId>,
+    root_features: &FeatureSet<'g>,
+    features: &FeatureSelection,
+) -> Result<FeatureSet<'g>, Error> {
+    let feature_graph = package_graph.feature_graph();
+    let root_crate_names = root_crate_names(package_graph, root_crates);
+    let mut feature_ids = Vec::new();
+    for selected in &features.features {
+        let (dependency, feature) = match selected.split_once('/') {
+            Some((package, _)) if root_crate_names.contains(package) => continue,
+            Some(dependency_feature) => dependency_feature,
+            None => continue,
+        };
+        let (dependency, weak) = match dependency.strip_suffix('?') {
+            Some(dependency) => (dependency, true),
+            None => (dependency, false),
+        };
+        let dep_links: Vec<_> = root_crates
+            .iter()
+            .filter_map(|root_crate| package_graph.metadata(root_crate).ok())
+            .flat_map(|package| package.direct_links())
+            .filter(|dep_link| dep_link.dep_name() == dependency)
+            .collect();
+        if dep_links.is_empty() {
+            return Err(Error::UnknownDependency(selected.clone()));
+        }
+        for dep_link in dep_links {
+            // an optional dependency is a feature of the root crate, named after the dependency
+            let optional_dependency = (dep_link.from().id(), dep_link.dep_name());
+            if feature_graph.metadata(optional_dependency).is_ok() {
+                if !weak {
+                    feature_ids.push(FeatureId::from(optional_dependency));
+                } else if !root_features.contains(optional_dependency)? {
+                    continue;
+                }
+            }
+            feature_ids.push(FeatureId::new(dep_link.to().id(), feature));
+        }
+    }
+    Ok(feature_graph.resolve_ids(feature_ids)?)
+}
+
+/// the feature resolver of the workspace, following cargo's rule: the `resolver` field of the
+/// workspace manifest if any, otherwise the new resolver if the root package of the workspace uses
+/// the 2021 edition (or a later one), and the old one in a virtual workspace
+fn resolver_version(package_graph: &PackageGraph) -> CargoResolverVersion {
+    let manifest_path = package_graph.workspace().root().join("Cargo.toml");
+    let manifest: Option<toml::Value> = std::fs::read_to_string(&manifest_path)
+        .ok()
+        .and_then(|manifest| toml::from_str(&manifest).ok());
+    let resolver = manifest.as_ref().and_then(|manifest| {
+        ["workspace", "package"]
+            .iter()
+            .find_map(|section| manifest.get(section)?.get("resolver")?.as_str())
+    });
+    match resolver {
+        Some("1") => return CargoResolverVersion::V1,
+        Some(_) => return CargoResolverVersion::V2,
+        None => (),
+    }
+    let new_edition = package_graph
+        .workspace()
+        .iter()
+        .find(|package| package.manifest_path() == manifest_path)
+        .and_then(|root_package| root_package.edition().parse::<u32>().ok())
+        .is_some_and(|edition| edition >= 2021);
+    if new_edition {
+        CargoResolverVersion::V2
+    } else {
+        CargoResolverVersion::V1
+    }
+}
+
+/// obtains the links that are activated in a feature set
+fn activated_links<'a, 'g: 'a>(
+    feature_set: &'a FeatureSet<'g>,
+) -> impl Iterator<Item = PackageLink<'g>> + 'a {
+    feature_set
+        .cross_links(DependencyDirection::Forward)
+        .map(|cross_link| cross_link.package_link())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use guppy::MetadataCommand;
+    use std::fs;
+    use std::path::Path;
+    use tempdir::TempDir;
+
+    /// writes a crate named `name` with the given manifest sections in `dir`
+    fn write_crate(dir: &Path, name: &str, sections: &str) {
+        let crate_dir = dir.join(name);
+        fs::create_dir_all(crate_dir.join("src")).unwrap();
+        let manifest = format!(
+            "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n{}",
+            name, sections
+        );
+        fs::write(crate_dir.join("Cargo.toml"), manifest).unwrap();
+        fs::write(crate_dir.join("src/lib.rs"), "").unwrap();
+    }
+
+    /// the packages and features (as `package/feature`) of the build of the `app` crate of `dir`
+    fn resolve_app(dir: &Path, features: &[&str]) -> Result<Vec<String>, Error> {
+        let mut cmd = MetadataCommand::new();
+        cmd.manifest_path(dir.join("app/Cargo.toml"));
+        let package_graph = cmd.build_graph().unwrap();
+        let root_crates: HashSet<PackageId> = package_graph
+            .packages()
+            .filter(|package| package.name() == "app")
+            .map(|package| package.id().clone())
+            .collect();
+        let features = FeatureSelection {
+            features: features.iter().map(|feature| feature.to_string()).collect(),
+            ..Default::default()
+        };
+        let resolved = ResolvedGraph::new(&package_graph, &root_crates, &features, &[], false)?;
+        let mut enabled: Vec<String> = resolved
+            .package_ids(DependencyDirection::Forward)
+            .flat_map(|package_id| {
+                let name = resolved.metadata(package_id).name();
+                std::iter::once(name.to_string()).chain(
+                    resolved
+                        .enabled_features(package_id)
+                        .into_iter()
+                        .map(move |feature| format!("{}/{}", name, feature)),
+                )
+            })
+            .collect();
+        enabled.sort();
+        Ok(enabled)
+    }
+
+    #[test]
+    fn dependency_features() {
+        // app -> a (optional)
+        // app -> b
+        let dir = TempDir::new("dephell_resolve").unwrap();
+        write_crate(
+            dir.path(),
+            "app",
+            "[dependencies]\na = { path = \"../a\", optional = true }\nb = { path = \"../b\" }\n",
+        );
+        write_crate(dir.path(), "a", "[features]\nx = []\n");
+        write_crate(dir.path(), "b", "[features]\ny = []\n");
+        let enabled = |features: &[&str]| resolve_app(dir.path(), features);
+
+        // the feature of a dependency is enabled, along with the optional dependency
+        assert_eq!(
+            enabled(&["a/x", "b/y"]).unwrap(),
+            ["a", "a/x", "app", "app/a", "b", "b/y"]
+        );
+        // a weak feature does not enable the optional dependency
+        assert_eq!(enabled(&["a?/x"]).unwrap(), ["app", "b"]);
+        assert_eq!(
+            enabled(&["app/a", "a?/x"]).unwrap(),
+            ["a", "a/x", "app", "app/a", "b"]
+        );
+        // a dependency that does not exist is an error
+        assert!(matches!(
+            enabled(&["d/x"]),
+            Err(Error::UnknownDependency(feature)) if feature == "d/x"
+        ));
+    }
+
+    #[test]
+    fn resolver_of_the_workspace_root() {
+        let dir = TempDir::new("dephell_resolve").unwrap();
+        let package = |name: &str, edition: &str| {
+            format!(
+                "[package]\nname = \"{}\"\nversion = \"0.1.0\"\nedition = \"{}\"\n",
+                name, edition
+            )
+        };
+        let resolver = |root_manifest: String| {
+            fs::write(dir.path().join("Cargo.toml"), root_manifest).unwrap();
+            let mut cmd = MetadataCommand::new();
+            cmd.manifest_path(dir.path().join("Cargo.toml"));
+            resolver_version(&cmd.build_graph().unwrap())
+        };
+        fs::create_dir_all(dir.path().join("src")).unwrap();
+        fs::write(dir.path().join("src/lib.rs"), "").unwrap();
+        fs::create_dir_all(dir.path().join("member/src")).unwrap();
+        fs::write(dir.path().join("member/src/lib.rs"), "").unwrap();
+        fs::write(
+            dir.path().join("member/Cargo.toml"),
+            package("member", "2021"),
+        )
+        .unwrap();
+        let workspace = "[workspace]\nmembers = [\"member\"]\n";
+
+        // the edition of a member does not matter, only the one of the root package
+        let root_2018 = format!("{}{}", package("root", "2018"), workspace);
+        assert_eq!(resolver(root_2018), CargoResolverVersion::V1);
+        let root_2021 = format!("{}{}", package("root", "2021"), workspace);
+        assert_eq!(resolver(root_2021), CargoResolverVersion::V2);
+        // a virtual workspace uses the old resolver unless told otherwise
+        assert_eq!(resolver(workspace.to_string()), CargoResolverVersion::V1);
+        let resolver_2 = format!("{}resolver = \"2\"\n", workspace);
+        assert_eq!(resolver(resolver_2), CargoResolverVersion::V2);
+    }
+}
@@ -0,0 +1,190 @@
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::PackageRisk;
+
+/// RiskScore is a normalized and explainable risk score.
+/// Each metric is normalized between 0 (no risk) and 1 (maximum risk),
+/// and the score is the weighted average of the normalized metrics, between 0 and 100.
+/// Metrics that were not collected are left out of the average.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct RiskScore {
+    /// the score, between 0 (no risk) and 100 (maximum risk)
+    pub score: f64,
+    /// the contribution of each metric to the score (they add up to the score)
+    pub contributions: BTreeMap<String, f64>,
+}
+
+/// how a metric is normalized
+enum Scale {
+    /// riskier when higher, the risk grows logarithmically up to this value
+    High(f64),
+    /// riskier when lower, the risk decreases logarithmically up to this value
+    Low(f64),
+    /// riskier when older, the risk grows linearly up to this number of days
+    Age(f64),
+}
+
+/// the metrics that can be part of the risk score, with how they are normalized
+/// and their default weight
+const METRICS: [(&str, Scale, f64); 13] = [
+    ("transitive_dependencies", Scale::High(100.0), 5.0),
+    ("root_importers", Scale::High(20.0), 1.0),
+    ("exclusive_deps_introduced", Scale::High(50.0), 5.0),
+    ("loc", Scale::High(100_000.0), 1.0),
+    ("total_loc", Scale::High(1_000_000.0), 0.0),
+    ("rust_loc", Scale::High(100_000.0), 5.0),
+    ("total_rust_loc", Scale::High(1_000_000.0), 0.0),
+    ("unsafe_loc", Scale::High(1_000.0), 1.0),
+    ("total_unsafe_loc", Scale::High(10_000.0), 0.0),
+    ("stargazers_count", Scale::Low(1_000.0), 1.0),
+    ("active_contributors", Scale::Low(10.0), 1.0),
+    ("crates_io_dependent", Scale::Low(1_000.0), 1.0),
+    ("crates_io_last_updated", Scale::Age(730.0), 1.0),
+];
+
+/// the default weight of each metric in the risk score
+pub fn default_weights() -> BTreeMap<String, f64> {
+    METRICS
+        .iter()
+        .map(|(metric, _, weight)| (metric.to_string(), *weight))
+        .collect()
+}
+
+/// is this a metric that can be part of the risk score?
+pub fn is_risk_metric(metric: &str) -> bool {
+    METRICS.iter().any(|(name, _, _)| *name == metric)
+}
+
+/// the value of a metric for a package, if it was collected
+fn metric_value(package_risk: &PackageRisk, metric: &str, today: NaiveDate) -> Option<f64> {
+    let value = match metric {
+        "transitive_dependencies" => package_risk.transitive_dependencies.len() as u64,
+        "root_importers" => package_risk.root_importers.len() as u64,
+        "exclusive_deps_introduced" => package_risk.exclusive_deps_introduced.len() as u64,
+        "loc" => package_risk.loc,
+        "total_loc" => package_risk.total_loc,
+        "rust_loc" => package_risk.rust_loc,
+        "total_rust_loc" => package_risk.total_rust_loc,
+        "unsafe_loc" => package_risk.unsafe_loc,
+        "total_unsafe_loc" => package_risk.total_unsafe_loc,
+        "stargazers_count" => package_risk.stargazers_count?,
+        "active_contributors" => package_risk.active_contributors?,
+        "crates_io_dependent" => package_risk.crates_io_dependent?,
+        "crates_io_last_updated" => {
+            let last_updated = package_risk.crates_io_last_updated?;
+            (today - last_updated).num_days().max(0) as u64
+        }
+        _ => return None,
+    };
+    Some(value as f64)
+}
+
+/// normalizes a value between 0 (no risk) and 1 (maximum risk)
+fn normalize(value: f64, scale: &Scale) -> f64 {
+    let log_ratio = |reference: f64| (value.ln_1p() / reference.ln_1p()).min(1.0);
+    match scale {
+        Scale::High(reference) => log_ratio(*reference),
+        Scale::Low(reference) => 1.0 - log_ratio(*reference),
+        Scale::Age(days) => (value / days).min(1.0),
+    }
+}
+
+/// Computes the risk score of a package with the given weights (see `default_weights`).
+/// The age of the last update is computed relative to `today`.
+pub fn risk_score(
+    package_risk: &PackageRisk,
+    weights: &BTreeMap<String, f64>,
+    today: NaiveDate,
+) -> RiskScore {
+    let mut weighted_risks = BTreeMap::new();
+    let mut total_weight = 0.0;
+    for (metric, scale, _) in METRICS.iter() {
+        let weight = weights.get(*metric).cloned().unwrap_or(0.0);
+        if weight <= 0.0 {
+            continue;
+        }
+        if let Some(value) = metric_value(package_risk, metric, today) {
+            weighted_risks.insert(metric.to_string(), weight * normalize(value, scale));
+            total_weight += weight;
+        }
+    }
+
+    // contributions are relative to the metrics that could be used
+    if total_weight == 0.0 {
+        return RiskScore::default();
+    }
+    let contributions: BTreeMap<String, f64> = weighted_risks
+        .into_iter()
+        .map(|(metric, weighted_risk)| (metric, round(100.0 * weighted_risk / total_weight)))
+        .collect();
+    RiskScore {
+        score: round(contributions.values().sum()),
+        contributions,
+    }
+}
+
+/// rounds to two decimals (to keep the output readable)
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalization() {
+        // logarithmic, capped at the reference value
+        assert_eq!(normalize(0.0, &Scale::High(100.0)), 0.0);
+        assert_eq!(normalize(100.0, &Scale::High(100.0)), 1.0);
+        assert_eq!(normalize(1_000.0, &Scale::High(100.0)), 1.0);
+        assert_eq!(normalize(9.0, &Scale::High(99.0)), 0.5);
+        // inverted for metrics that are riskier when lower
+        assert_eq!(normalize(0.0, &Scale::Low(100.0)), 1.0);
+        assert_eq!(normalize(9.0, &Scale::Low(99.0)), 0.5);
+        assert_eq!(normalize(1_000.0, &Scale::Low(100.0)), 0.0);
+        // linear for ages
+        assert_eq!(normalize(365.0, &Scale::Age(730.0)), 0.5);
+        assert_eq!(normalize(1_000.0, &Scale::Age(730.0)), 1.0);
+    }
+
+    #[test]
+    fn score() {
+        let today = NaiveDate::from_ymd(2021, 3, 1);
+        let package_risk = PackageRisk {
+            rust_loc: 100_000,
+            stargazers_count: Some(1_000),
+            ..PackageRisk::default()
+        };
+        let weights: BTreeMap<String, f64> = [
+            ("rust_loc", 3.0),
+            ("stargazers_count", 1.0),
+            ("crates_io_last_updated", 2.0),
+            ("loc", 0.0),
+        ]
+        .iter()
+        .map(|(metric, weight)| (metric.to_string(), *weight))
+        .collect();
+
+        // metrics that were not collected (last update) or have no weight (loc) are left out
+        let risk = risk_score(&package_risk, &weights, today);
+        assert_eq!(risk.score, 75.0);
+        let contributions: Vec<(&str, f64)> = risk
+            .contributions
+            .iter()
+            .map(|(metric, contribution)| (metric.as_str(), *contribution))
+            .collect();
+        assert_eq!(
+            contributions,
+            vec![("rust_loc", 75.0), ("stargazers_count", 0.0)]
+        );
+
+        // no metric, no risk
+        let risk = risk_score(&package_risk, &BTreeMap::new(), today);
+        assert_eq!(risk.score, 0.0);
+        assert!(risk.contributions.is_empty());
+    }
+}
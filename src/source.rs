@@ -0,0 +1,363 @@
+use camino::Utf8Path;
+use regex::Regex;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use std::process::Stdio;
+use std::sync::Mutex;
+
+use crate::diff::{git, run_git};
+use crate::metrics::{self, VcsInfo};
+use crate::Error;
+
+/// the files that cargo adds when packaging (or extracting) a crate, they are not in the repository
+const GENERATED_FILES: [&str; 4] = [
+    ".cargo_vcs_info.json",
+    ".cargo-ok",
+    "Cargo.toml",
+    "Cargo.lock",
+];
+
+/// the file in which cargo keeps the original `Cargo.toml` when packaging a crate
+const ORIGINAL_MANIFEST: &str = "Cargo.toml.orig";
+
+/// SourceStatus is the result of comparing a published crate with its repository.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum SourceStatus {
+    /// every published file is in the repository, with the same content
+    Verified,
+    /// some published files differ from the repository, or are not in it
+    Mismatched,
+    /// the published crate could not be compared with its repository (see the reason)
+    Unverifiable,
+}
+
+/// SourceVerification compares the files of a crate published on crates.io
+/// with its repository, at the commit it was published from.
+/// Paths are relative to the folder of the crate.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SourceVerification {
+    /// the result of the comparison
+    pub status: SourceStatus,
+    /// the commit the crate was published from (according to `.cargo_vcs_info.json`)
+    pub commit: Option<String>,
+    /// the folder of the crate in the repository
+    pub path_in_vcs: Option<String>,
+    /// the published files whose content differs from the repository
+    pub mismatched: Vec<String>,
+    /// the published files that are not in the repository
+    pub added: Vec<String>,
+    /// the files of the repository that were not published
+    /// (this is expected for files excluded from the package)
+    pub missing: Vec<String>,
+    /// why the crate could not be compared with its repository
+    pub reason: Option<String>,
+}
+
+impl SourceVerification {
+    fn unverifiable(reason: impl Into<String>) -> Self {
+        Self {
+            status: SourceStatus::Unverifiable,
+            commit: None,
+            path_in_vcs: None,
+            mismatched: Vec::new(),
+            added: Vec::new(),
+            missing: Vec::new(),
+            reason: Some(reason.into()),
+        }
+    }
+}
+
+/// SourceVerifier compares published crates with clones of their repositories, found in a
+/// mirror folder or cloned (without their files' content) in a folder of its own.
+pub struct SourceVerifier {
+    /// a folder containing clones of repositories, named after the repository or the crate
+    mirror: Option<PathBuf>,
+    /// where repositories are cloned when there is no mirror
+    clone_dir: Option<PathBuf>,
+    /// used to find the URL of github repositories (links often point inside the repository)
+    github_repo_regex: Regex,
+    /// clones and fetches are done one at a time (crates can share a repository)
+    fetch_lock: Mutex<()>,
+}
+
+impl SourceVerifier {
+    /// uses the clones found in `mirror` if given, otherwise clones repositories in `clone_dir`
+    pub fn new(mirror: Option<PathBuf>, clone_dir: Option<PathBuf>) -> Self {
+        Self {
+            mirror,
+            clone_dir,
+            github_repo_regex: Regex::new(r"github\.com/([a-zA-Z0-9._-]+/[a-zA-Z0-9._-]+)")
+                .unwrap(),
+            fetch_lock: Mutex::new(()),
+        }
+    }
+
+    /// compares a published crate (its `Cargo.toml` being at `manifest_path`)
+    /// with its repository
+    pub fn verify(
+        &self,
+        name: &str,
+        repository: Option<&str>,
+        manifest_path: &Utf8Path,
+    ) -> SourceVerification {
+        let vcs_info = match metrics::get_vcs_info(manifest_path) {
+            Some(vcs_info) => vcs_info,
+            None => {
+                return SourceVerification::unverifiable(
+                    "the published crate has no .cargo_vcs_info.json",
+                )
+            }
+        };
+        let package_dir = manifest_path.parent().unwrap_or_else(|| Utf8Path::new(""));
+        let result = self
+            .find_clone(name, repository)
+            .and_then(|clone| self.compare(&clone, &vcs_info, package_dir.as_ref()));
+        let mut verification = match result {
+            Ok(verification) => verification,
+            Err(reason) => SourceVerification::unverifiable(reason),
+        };
+        verification.commit = Some(vcs_info.git.sha1);
+        verification.path_in_vcs = Some(vcs_info.path_in_vcs);
+        verification
+    }
+
+    /// the clone of the repository of a crate, cloned if needed
+    fn find_clone(&self, name: &str, repository: Option<&str>) -> Result<PathBuf, String> {
+        let url = repository.map(|repository| self.clone_url(repository));
+
+        // a mirror contains clones named after the repository, or the crate
+        if let Some(mirror) = &self.mirror {
+            let repo_name = url.as_deref().and_then(|url| url.rsplit('/').next());
+            return repo_name
+                .into_iter()
+                .chain(std::iter::once(name))
+                .map(|clone_name| mirror.join(clone_name.trim_end_matches(".git")))
+                .find(|clone| clone.is_dir())
+                .ok_or_else(|| format!("no clone of the repository in {}", mirror.display()));
+        }
+
+        let url = url.ok_or("no repository")?;
+        let clone_dir = self
+            .clone_dir
+            .as_ref()
+            .ok_or("no clone of the repository")?;
+        // (git runs in `clone_dir`, so the clone path must not be relative to the current one)
+        let clone_dir = std::env::current_dir()
+            .map_err(|err| err.to_string())?
+            .join(clone_dir);
+        let clone = clone_dir.join(clone_path(&url));
+        let _lock = self.fetch_lock.lock().unwrap();
+        if !clone.is_dir() {
+            std::fs::create_dir_all(&clone_dir).map_err(|err| err.to_string())?;
+            // only the commits and trees are needed, not the content of the files
+            run_git(
+                git(&clone_dir)
+                    .env("GIT_TERMINAL_PROMPT", "0")
+                    .args(["clone", "--quiet", "--bare", "--filter=blob:none"])
+                    .arg(&url)
+                    .arg(&clone),
+            )
+            .map_err(|err| format!("could not clone {}: {}", url, git_error(err)))?;
+        }
+        Ok(clone)
+    }
+
+    /// the URL of a repository as it can be cloned (links to github repositories
+    /// often point to a folder of the repository)
+    fn clone_url(&self, repository: &str) -> String {
+        match self
+            .github_repo_regex
+            .captures(repository)
+            .and_then(|caps| caps.get(1))
+        {
+            Some(repo_name) => format!(
+                "https://github.com/{}",
+                repo_name.as_str().trim_end_matches(".git")
+            ),
+            None => repository.trim().trim_end_matches('/').to_string(),
+        }
+    }
+
+    /// compares the files of the published crate in `package_dir` with the repository
+    fn compare(
+        &self,
+        clone: &Path,
+        vcs_info: &VcsInfo,
+        package_dir: &Path,
+    ) -> Result<SourceVerification, String> {
+        let commit = &vcs_info.git.sha1;
+        let commit_exists = |clone: &Path| {
+            run_git(git(clone).args(["cat-file", "-e", &format!("{}^{{commit}}", commit)])).is_ok()
+        };
+        if !commit_exists(clone) {
+            // the commit might have been pushed after the repository was cloned
+            if self.mirror.is_none() {
+                let _lock = self.fetch_lock.lock().unwrap();
+                let _ = run_git(git(clone).env("GIT_TERMINAL_PROMPT", "0").args([
+                    "fetch",
+                    "--quiet",
+                    "--filter=blob:none",
+                    "origin",
+                    commit,
+                ]));
+            }
+            if !commit_exists(clone) {
+                return Err(format!("commit {} not found in the repository", commit));
+            }
+        }
+
+        // the files of the crate in the repository: path -> (mode, hash)
+        let mut ls_tree = git(clone);
+        ls_tree.args(["ls-tree", "-r", "-z", "--full-tree", commit]);
+        if !vcs_info.path_in_vcs.is_empty() {
+            ls_tree.arg("--").arg(&vcs_info.path_in_vcs);
+        }
+        let tree = run_git(&mut ls_tree).map_err(git_error)?;
+        let prefix = match vcs_info.path_in_vcs.as_str() {
+            "" => String::new(),
+            path_in_vcs => format!("{}/", path_in_vcs),
+        };
+        let mut repo_files: BTreeMap<String, (String, String)> = BTreeMap::new();
+        for entry in tree.split('\0').filter(|entry| !entry.is_empty()) {
+            let (info, path) = entry
+                .split_once('\t')
+                .ok_or_else(|| format!("unexpected git ls-tree output: {}", entry))?;
+            let info: Vec<&str> = info.split(' ').collect();
+            if let ([mode, _, hash], Some(path)) = (&info[..], path.strip_prefix(&prefix)) {
+                repo_files.insert(path.to_string(), (mode.to_string(), hash.to_string()));
+            }
+        }
+
+        // the files of the published crate (the original `Cargo.toml` is kept by cargo)
+        let mut package_files: BTreeMap<String, PathBuf> = BTreeMap::new();
+        for file in ignore::WalkBuilder::new(package_dir)
+            .standard_filters(false)
+            .build()
+        {
+            let file = file.map_err(|err| err.to_string())?;
+            if !file
+                .file_type()
+                .is_some_and(|file_type| file_type.is_file())
+            {
+                continue;
+            }
+            let relative_path = match file.path().strip_prefix(package_dir) {
+                Ok(relative_path) => relative_path,
+                Err(_) => continue,
+            };
+            let relative_path: Vec<&str> = relative_path
+                .components()
+                .filter_map(|component| match component {
+                    Component::Normal(component) => component.to_str(),
+                    _ => None,
+                })
+                .collect();
+            let relative_path = match relative_path.join("/") {
+                path if path == ORIGINAL_MANIFEST => "Cargo.toml".to_string(),
+                path if GENERATED_FILES.contains(&path.as_str()) => continue,
+                path => path,
+            };
+            package_files.insert(relative_path, file.path().to_path_buf());
+        }
+
+        // compare the git hashes of the files
+        let hashes = hash_files(clone, package_files.values())?;
+        let mut verification = SourceVerification {
+            status: SourceStatus::Verified,
+            commit: None,
+            path_in_vcs: None,
+            mismatched: Vec::new(),
+            added: Vec::new(),
+            missing: Vec::new(),
+            reason: None,
+        };
+        for (path, hash) in package_files.keys().zip(hashes) {
+            match repo_files.get(path) {
+                // symbolic links are followed by cargo, their content can't be compared
+                Some((mode, _)) if mode == "120000" => (),
+                Some((_, repo_hash)) if *repo_hash == hash => (),
+                Some(_) => verification.mismatched.push(path.clone()),
+                None => verification.added.push(path.clone()),
+            }
+        }
+
+        // nested packages and lockfiles are never published
+        let nested_packages: BTreeSet<&str> = repo_files
+            .keys()
+            .filter_map(|path| path.strip_suffix("/Cargo.toml"))
+            .collect();
+        verification.missing = repo_files
+            .keys()
+            .filter(|path| !package_files.contains_key(*path))
+            .filter(|path| !GENERATED_FILES.contains(&path.as_str()) || *path == "Cargo.toml")
+            .filter(|path| {
+                !nested_packages
+                    .iter()
+                    .any(|nested| path.starts_with(&format!("{}/", nested)))
+            })
+            .cloned()
+            .collect();
+
+        if !verification.mismatched.is_empty() || !verification.added.is_empty() {
+            verification.status = SourceStatus::Mismatched;
+        }
+        Ok(verification)
+    }
+}
+
+/// the git hashes of some files (as they would be stored in git)
+fn hash_files<'a>(
+    clone: &Path,
+    files: impl Iterator<Item = &'a PathBuf>,
+) -> Result<Vec<String>, String> {
+    let mut paths = String::new();
+    for file in files {
+        let file = file
+            .to_str()
+            .ok_or_else(|| format!("{} is not valid UTF-8", file.display()))?;
+        paths.push_str(file);
+        paths.push('\n');
+    }
+    let mut child = git(clone)
+        .args(["hash-object", "--no-filters", "--stdin-paths"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|err| err.to_string())?;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(paths.as_bytes())
+        .map_err(|err| err.to_string())?;
+    let output = child.wait_with_output().map_err(|err| err.to_string())?;
+    if !output.status.success() {
+        return Err(String::from_utf8_lossy(&output.stderr).into_owned());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// the reason a git command failed
+fn git_error(err: Error) -> String {
+    match err {
+        Error::Git(stderr) => stderr.trim().to_string(),
+        err => err.to_string(),
+    }
+}
+
+/// where a repository is cloned, relative to the folder of clones
+/// (`https://github.com/owner/repo` is cloned in `github.com/owner/repo`)
+fn clone_path(url: &str) -> PathBuf {
+    let url = url.split_once("://").map_or(url, |(_, rest)| rest);
+    url.split(['/', ':'])
+        .filter(|component| !component.is_empty() && *component != "." && *component != "..")
+        .collect()
+}
@@ -0,0 +1,153 @@
+use proc_macro2::Span;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use syn::visit::{self, Visit};
+
+/// UnsafeMetrics counts the unsafe constructs found in the rust files of a package.
+/// Code generated by macros is not seen (only the source files are parsed).
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct UnsafeMetrics {
+    /// number of `unsafe { }` blocks
+    pub unsafe_blocks: u64,
+    /// number of `unsafe fn` (functions and methods)
+    pub unsafe_fns: u64,
+    /// number of `unsafe impl`
+    pub unsafe_impls: u64,
+    /// number of `unsafe trait`
+    pub unsafe_traits: u64,
+    /// number of items exported with `#[no_mangle]`
+    pub no_mangle_items: u64,
+    /// number of items using a foreign ABI (items of `extern` blocks and `extern "C" fn`)
+    pub extern_items: u64,
+    /// number of rust files that could not be parsed (their unsafe code is not counted)
+    pub unparsed_files: u64,
+}
+
+impl UnsafeMetrics {
+    /// adds the metrics of another package
+    pub fn add(&mut self, other: &UnsafeMetrics) {
+        self.unsafe_blocks += other.unsafe_blocks;
+        self.unsafe_fns += other.unsafe_fns;
+        self.unsafe_impls += other.unsafe_impls;
+        self.unsafe_traits += other.unsafe_traits;
+        self.no_mangle_items += other.no_mangle_items;
+        self.extern_items += other.extern_items;
+        self.unparsed_files += other.unparsed_files;
+    }
+}
+
+/// finds the unsafe constructs of a rust file, and returns the number of lines
+/// in unsafe regions (unsafe blocks and the bodies of unsafe functions)
+pub fn analyze_file(path: &str, metrics: &mut UnsafeMetrics) -> u64 {
+    let file = match fs::read_to_string(path)
+        .ok()
+        .and_then(|content| syn::parse_file(&content).ok())
+    {
+        Some(file) => file,
+        None => {
+            metrics.unparsed_files += 1;
+            return 0;
+        }
+    };
+    let mut visitor = UnsafeVisitor {
+        metrics,
+        unsafe_lines: BTreeSet::new(),
+    };
+    visitor.visit_file(&file);
+    visitor.unsafe_lines.len() as u64
+}
+
+struct UnsafeVisitor<'a> {
+    metrics: &'a mut UnsafeMetrics,
+    /// the lines in unsafe regions (nested regions are counted once)
+    unsafe_lines: BTreeSet<usize>,
+}
+
+impl UnsafeVisitor<'_> {
+    fn add_lines(&mut self, start: Span, end: Span) {
+        self.unsafe_lines
+            .extend(start.start().line..=end.end().line);
+    }
+
+    fn add_fn(&mut self, sig: &syn::Signature, block: Option<&syn::Block>) {
+        if sig.unsafety.is_some() {
+            self.metrics.unsafe_fns += 1;
+            if let Some(block) = block {
+                let span = block.brace_token.span;
+                self.add_lines(span.open(), span.close());
+            }
+        }
+        let foreign_abi = sig.abi.as_ref().map(|abi| match &abi.name {
+            Some(name) => name.value() != "Rust",
+            None => true,
+        });
+        if foreign_abi == Some(true) {
+            self.metrics.extern_items += 1;
+        }
+    }
+
+    fn add_attrs(&mut self, attrs: &[syn::Attribute]) {
+        // `#[no_mangle]`, or `#[unsafe(no_mangle)]` since the 2024 edition
+        let no_mangle = attrs.iter().any(|attr| {
+            attr.path().is_ident("no_mangle")
+                || (attr.path().is_ident("unsafe")
+                    && attr
+                        .parse_args::<syn::Path>()
+                        .is_ok_and(|path| path.is_ident("no_mangle")))
+        });
+        if no_mangle {
+            self.metrics.no_mangle_items += 1;
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for UnsafeVisitor<'_> {
+    fn visit_expr_unsafe(&mut self, expr: &'ast syn::ExprUnsafe) {
+        self.metrics.unsafe_blocks += 1;
+        self.add_lines(expr.unsafe_token.span, expr.block.brace_token.span.close());
+        visit::visit_expr_unsafe(self, expr);
+    }
+
+    fn visit_item_fn(&mut self, item: &'ast syn::ItemFn) {
+        self.add_fn(&item.sig, Some(&item.block));
+        self.add_attrs(&item.attrs);
+        visit::visit_item_fn(self, item);
+    }
+
+    fn visit_impl_item_fn(&mut self, item: &'ast syn::ImplItemFn) {
+        self.add_fn(&item.sig, Some(&item.block));
+        self.add_attrs(&item.attrs);
+        visit::visit_impl_item_fn(self, item);
+    }
+
+    fn visit_trait_item_fn(&mut self, item: &'ast syn::TraitItemFn) {
+        self.add_fn(&item.sig, item.default.as_ref());
+        visit::visit_trait_item_fn(self, item);
+    }
+
+    fn visit_item_static(&mut self, item: &'ast syn::ItemStatic) {
+        self.add_attrs(&item.attrs);
+        visit::visit_item_static(self, item);
+    }
+
+    fn visit_item_impl(&mut self, item: &'ast syn::ItemImpl) {
+        if item.unsafety.is_some() {
+            self.metrics.unsafe_impls += 1;
+        }
+        visit::visit_item_impl(self, item);
+    }
+
+    fn visit_item_trait(&mut self, item: &'ast syn::ItemTrait) {
+        if item.unsafety.is_some() {
+            self.metrics.unsafe_traits += 1;
+        }
+        visit::visit_item_trait(self, item);
+    }
+
+    fn visit_item_foreign_mod(&mut self, item: &'ast syn::ItemForeignMod) {
+        self.metrics.extern_items += item.items.len() as u64;
+        visit::visit_item_foreign_mod(self, item);
+    }
+}
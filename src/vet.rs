@@ -0,0 +1,474 @@
+use chrono::NaiveDate;
+use schemars::JsonSchema;
+use semver::{Version, VersionReq};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::path::Path;
+
+use crate::Error;
+
+/// the name of the folder containing the cargo-vet files, looked up next to the analyzed `Cargo.toml`
+pub const VET_DIR_NAME: &str = "supply-chain";
+
+/// the criteria required for dependencies that are only used in tests
+pub const SAFE_TO_RUN: &str = "safe-to-run";
+
+/// the criteria required for every other dependency
+pub const SAFE_TO_DEPLOY: &str = "safe-to-deploy";
+
+/// VetStatus is what cargo-vet knows about a version of a dependency.
+#[derive(Debug, Default, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct VetStatus {
+    /// the criteria this version is certified for by audits (including imported audits
+    /// and trusted publishers), along with the criteria they imply
+    pub certified: BTreeSet<String>,
+    /// the criteria this version is exempted from being audited for
+    pub exempted: BTreeSet<String>,
+    /// is this version listed as violating some criteria?
+    pub violation: bool,
+    /// the criteria this dependency must satisfy
+    /// (`safe-to-run` for dev dependencies, `safe-to-deploy` otherwise)
+    pub required: String,
+    /// is the required criteria certified or exempted (and not violated)?
+    pub vetted: bool,
+}
+
+/// a string or a list of strings
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
+}
+
+impl Default for OneOrMany {
+    fn default() -> Self {
+        OneOrMany::Many(Vec::new())
+    }
+}
+
+impl OneOrMany {
+    fn into_vec(self) -> Vec<String> {
+        match self {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
+        }
+    }
+}
+
+/// `audits.toml`, or an import of `imports.lock` (only what we use)
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct AuditsFile {
+    criteria: HashMap<String, CriteriaEntry>,
+    audits: HashMap<String, Vec<AuditEntry>>,
+    trusted: HashMap<String, Vec<TrustedEntry>>,
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct CriteriaEntry {
+    implies: OneOrMany,
+}
+
+#[derive(Deserialize)]
+struct AuditEntry {
+    #[serde(default)]
+    criteria: OneOrMany,
+    version: Option<String>,
+    delta: Option<String>,
+    violation: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TrustedEntry {
+    #[serde(default)]
+    criteria: OneOrMany,
+    user_id: u64,
+    start: String,
+    end: String,
+}
+
+/// `config.toml` (only what we use)
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ConfigFile {
+    exemptions: HashMap<String, Vec<ExemptionEntry>>,
+}
+
+#[derive(Deserialize)]
+struct ExemptionEntry {
+    version: String,
+    #[serde(default)]
+    criteria: OneOrMany,
+}
+
+/// `imports.lock` (only what we use)
+#[derive(Default, Deserialize)]
+#[serde(default)]
+struct ImportsFile {
+    audits: HashMap<String, AuditsFile>,
+    publisher: HashMap<String, Vec<PublisherEntry>>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct PublisherEntry {
+    version: String,
+    when: String,
+    user_id: u64,
+}
+
+/// an audit of a crate, as a full audit of `to`, or a delta audit from `from` to `to`
+struct Certification {
+    from: Option<String>,
+    to: String,
+    criteria: Vec<String>,
+}
+
+/// VetStore holds the audits, exemptions and trusted publishers recorded
+/// in a cargo-vet store (a `supply-chain` folder).
+#[derive(Default)]
+pub struct VetStore {
+    /// criteria -> criteria they directly imply
+    implies: HashMap<String, Vec<String>>,
+    /// crate name -> audits (including imported ones)
+    certifications: HashMap<String, Vec<Certification>>,
+    /// crate name -> (version, criteria) of exemptions
+    exemptions: HashMap<String, Vec<(String, Vec<String>)>>,
+    /// crate name -> versions violating some criteria
+    violations: HashMap<String, Vec<VersionReq>>,
+}
+
+impl VetStore {
+    /// reads the `audits.toml`, `config.toml` and `imports.lock` of a cargo-vet store
+    pub fn open(path: &Path) -> Result<Self, Error> {
+        fn read<T: Default + for<'de> Deserialize<'de>>(path: &Path) -> Result<T, Error> {
+            if !path.is_file() {
+                return Ok(T::default());
+            }
+            let content = fs::read_to_string(path)?;
+            toml::from_str(&content).map_err(|err| Error::Vet(path.to_path_buf(), err.to_string()))
+        }
+        let audits: AuditsFile = read(&path.join("audits.toml"))?;
+        let config: ConfigFile = read(&path.join("config.toml"))?;
+        let imports: ImportsFile = read(&path.join("imports.lock"))?;
+
+        let mut store = Self::default();
+        store
+            .add_audits(audits, None, &imports.publisher)
+            .map_err(|err| Error::Vet(path.join("audits.toml"), err))?;
+        for (import, audits) in imports.audits {
+            store
+                .add_audits(audits, Some(&import), &imports.publisher)
+                .map_err(|err| Error::Vet(path.join("imports.lock"), err))?;
+        }
+        for (name, exemptions) in config.exemptions {
+            store.exemptions.entry(name).or_default().extend(
+                exemptions
+                    .into_iter()
+                    .map(|exemption| (exemption.version, exemption.criteria.into_vec())),
+            );
+        }
+        Ok(store)
+    }
+
+    /// adds the audits of our store, or of an import (its custom criteria are then
+    /// prefixed with the name of the import)
+    fn add_audits(
+        &mut self,
+        audits: AuditsFile,
+        import: Option<&str>,
+        publishers: &HashMap<String, Vec<PublisherEntry>>,
+    ) -> Result<(), String> {
+        let custom_criteria: BTreeSet<String> = audits.criteria.keys().cloned().collect();
+        let rename = |criteria: Vec<String>| -> Vec<String> {
+            criteria
+                .into_iter()
+                .map(|criteria| match import {
+                    Some(import) if custom_criteria.contains(&criteria) => {
+                        format!("{}::{}", import, criteria)
+                    }
+                    _ => criteria,
+                })
+                .collect()
+        };
+
+        for (criteria, entry) in audits.criteria {
+            let criteria = rename(vec![criteria]).remove(0);
+            self.implies
+                .insert(criteria, rename(entry.implies.into_vec()));
+        }
+
+        for (name, entries) in audits.audits {
+            for entry in entries {
+                let criteria = rename(entry.criteria.into_vec());
+                if let Some(violation) = entry.violation {
+                    let violation = VersionReq::parse(&violation)
+                        .map_err(|err| format!("{}: {}", violation, err))?;
+                    self.violations
+                        .entry(name.clone())
+                        .or_default()
+                        .push(violation);
+                } else if let Some(delta) = entry.delta {
+                    let (from, to) = delta
+                        .split_once("->")
+                        .ok_or_else(|| format!("invalid delta {}", delta))?;
+                    self.certifications
+                        .entry(name.clone())
+                        .or_default()
+                        .push(Certification {
+                            from: Some(from.trim().to_string()),
+                            to: to.trim().to_string(),
+                            criteria,
+                        });
+                } else if let Some(version) = entry.version {
+                    self.certifications
+                        .entry(name.clone())
+                        .or_default()
+                        .push(Certification {
+                            from: None,
+                            to: version,
+                            criteria,
+                        });
+                }
+            }
+        }
+
+        // versions published by a trusted user during the trusted period
+        for (name, trusted_entries) in audits.trusted {
+            for trusted in trusted_entries {
+                let criteria = rename(trusted.criteria.into_vec());
+                let (start, end) = (parse_date(&trusted.start)?, parse_date(&trusted.end)?);
+                for publisher in publishers.get(&name).into_iter().flatten() {
+                    let when = parse_date(&publisher.when)?;
+                    if publisher.user_id == trusted.user_id && start <= when && when < end {
+                        self.certifications
+                            .entry(name.clone())
+                            .or_default()
+                            .push(Certification {
+                                from: None,
+                                to: publisher.version.clone(),
+                                criteria: criteria.clone(),
+                            });
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// the criteria, and all the criteria they imply
+    fn with_implied(&self, criteria: impl IntoIterator<Item = String>) -> BTreeSet<String> {
+        let mut all = BTreeSet::new();
+        let mut to_visit: Vec<String> = criteria.into_iter().collect();
+        while let Some(criteria) = to_visit.pop() {
+            if let Some(implied) = self.implies.get(&criteria) {
+                to_visit.extend(implied.iter().cloned());
+            }
+            if criteria == SAFE_TO_DEPLOY {
+                to_visit.push(SAFE_TO_RUN.to_string());
+            }
+            all.insert(criteria);
+        }
+        all
+    }
+
+    /// is `version` reachable for `criteria` from a full audit, through delta audits?
+    fn is_certified(
+        &self,
+        certifications: &[Certification],
+        version: &str,
+        criteria: &str,
+    ) -> bool {
+        let covers = |certification: &&Certification| {
+            self.with_implied(certification.criteria.iter().cloned())
+                .contains(criteria)
+        };
+        let mut to_visit: VecDeque<&str> = certifications
+            .iter()
+            .filter(covers)
+            .filter(|certification| certification.from.is_none())
+            .map(|certification| certification.to.as_str())
+            .collect();
+        let mut visited = BTreeSet::new();
+        while let Some(audited) = to_visit.pop_front() {
+            if audited == version {
+                return true;
+            }
+            if !visited.insert(audited) {
+                continue;
+            }
+            to_visit.extend(
+                certifications
+                    .iter()
+                    .filter(covers)
+                    .filter(|certification| certification.from.as_deref() == Some(audited))
+                    .map(|certification| certification.to.as_str()),
+            );
+        }
+        false
+    }
+
+    /// what the store knows about a version of a crate,
+    /// `dev_only` being whether the crate is only used as a dev dependency
+    pub fn status(&self, name: &str, version: &str, dev_only: bool) -> VetStatus {
+        let certifications = self
+            .certifications
+            .get(name)
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        let all_criteria = self.with_implied(
+            certifications
+                .iter()
+                .flat_map(|c| c.criteria.iter().cloned()),
+        );
+        let certified: BTreeSet<String> = all_criteria
+            .into_iter()
+            .filter(|criteria| self.is_certified(certifications, version, criteria))
+            .collect();
+        let exempted = self.with_implied(
+            self.exemptions
+                .get(name)
+                .into_iter()
+                .flatten()
+                .filter(|(exempted_version, _)| exempted_version == version)
+                .flat_map(|(_, criteria)| criteria.iter().cloned()),
+        );
+        let violation = Version::parse(version)
+            .map(|version| {
+                self.violations
+                    .get(name)
+                    .into_iter()
+                    .flatten()
+                    .any(|violation| violation.matches(&version))
+            })
+            .unwrap_or(false);
+        let required = if dev_only {
+            SAFE_TO_RUN
+        } else {
+            SAFE_TO_DEPLOY
+        };
+        VetStatus {
+            vetted: !violation && (certified.contains(required) || exempted.contains(required)),
+            certified,
+            exempted,
+            violation,
+            required: required.to_string(),
+        }
+    }
+}
+
+/// dates of cargo-vet are `YYYY-MM-DD`, possibly followed by a time
+fn parse_date(date: &str) -> Result<NaiveDate, String> {
+    let day = date.get(..10).unwrap_or(date);
+    day.parse()
+        .map_err(|err| format!("invalid date {}: {}", date, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempdir::TempDir;
+
+    /// a cargo-vet store with the given `audits.toml` and `config.toml`
+    fn store(audits: &str, config: &str) -> VetStore {
+        let dir = TempDir::new("dephell_vet").unwrap();
+        fs::write(dir.path().join("audits.toml"), audits).unwrap();
+        fs::write(dir.path().join("config.toml"), config).unwrap();
+        VetStore::open(dir.path()).unwrap()
+    }
+
+    fn criteria(status: &BTreeSet<String>) -> Vec<&str> {
+        status.iter().map(String::as_str).collect()
+    }
+
+    #[test]
+    fn delta_chains() {
+        let store = store(
+            r#"
+[criteria.reviewed]
+description = "reviewed"
+implies = "safe-to-deploy"
+
+[[audits.foo]]
+criteria = "reviewed"
+version = "1.0.0"
+
+[[audits.foo]]
+criteria = "reviewed"
+delta = "1.0.0 -> 1.1.0"
+
+[[audits.foo]]
+criteria = "safe-to-run"
+delta = "1.1.0 -> 1.2.0"
+
+# not reachable from a full audit
+[[audits.foo]]
+criteria = "reviewed"
+delta = "1.5.0 -> 2.0.0"
+"#,
+            "",
+        );
+
+        // a full audit, and the criteria it implies
+        let status = store.status("foo", "1.0.0", false);
+        assert_eq!(
+            criteria(&status.certified),
+            vec!["reviewed", "safe-to-deploy", "safe-to-run"]
+        );
+        assert!(status.vetted);
+        // through a delta audit
+        let status = store.status("foo", "1.1.0", false);
+        assert_eq!(
+            criteria(&status.certified),
+            vec!["reviewed", "safe-to-deploy", "safe-to-run"]
+        );
+        // a chain is only as strong as its weakest delta
+        let status = store.status("foo", "1.2.0", false);
+        assert_eq!(criteria(&status.certified), vec!["safe-to-run"]);
+        assert!(!status.vetted);
+        assert_eq!(status.required, "safe-to-deploy");
+        assert!(store.status("foo", "1.2.0", true).vetted);
+        // a delta audit without a full audit to start from certifies nothing
+        let status = store.status("foo", "2.0.0", false);
+        assert!(status.certified.is_empty());
+        assert!(!status.vetted);
+    }
+
+    #[test]
+    fn exemptions_and_violations() {
+        let store = store(
+            r#"
+[[audits.foo]]
+criteria = "safe-to-deploy"
+version = "1.0.0"
+
+[[audits.foo]]
+criteria = "safe-to-deploy"
+violation = ">= 1.0.0, < 1.1.0"
+"#,
+            r#"
+[[exemptions.bar]]
+version = "0.3.0"
+criteria = "safe-to-deploy"
+"#,
+        );
+
+        let status = store.status("bar", "0.3.0", false);
+        assert!(status.certified.is_empty());
+        assert_eq!(
+            criteria(&status.exempted),
+            vec!["safe-to-deploy", "safe-to-run"]
+        );
+        assert!(status.vetted);
+        assert!(!store.status("bar", "0.4.0", false).vetted);
+
+        // a violation wins over audits
+        let status = store.status("foo", "1.0.0", false);
+        assert!(status.violation);
+        assert!(!status.vetted);
+    }
+}